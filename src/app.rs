@@ -0,0 +1,605 @@
+use std::io::{BufRead, IsTerminal};
+use std::path::Path;
+
+use crate::cli::{AffectedOptions, BaseGraph, GraphOptions, Operation, Opts};
+use crate::lock::Lockfile;
+use crate::manifest::Manifest;
+use crate::run::RunCommand;
+use crate::service::Service;
+use crate::state::BuildState;
+use crate::{
+    cache, closure, dependency, fingerprint, graph, lock, output, report, run, service, watch,
+};
+
+use anyhow::{Result, anyhow};
+use env_logger::Env;
+
+/// Entrypoint of the `monodeps` command line interface: parses the process' command line
+/// arguments and runs the requested operation. Exits the process on any error.
+pub fn main() {
+    // parse CLI arguments
+    let (operation, opts) = bail_out(Opts::parse());
+
+    // by default we write all WARN logs on stderr (no timestamp or logger name)
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn"))
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+
+    match operation {
+        Operation::Dependencies {
+            affected,
+            github_output,
+        } => dependencies(std::io::stdin().lock(), opts, &affected, github_output),
+        Operation::Validate(path) => validate(&path, opts),
+        Operation::Graph { diff, options } => graph(std::io::stdin().lock(), opts, diff, options),
+        Operation::CacheClean => cache_clean(opts),
+        Operation::Watch => watch(opts),
+        Operation::Lock { check } => lockfile(opts, check),
+        Operation::Report { html } => report(&html, opts),
+        Operation::Files {
+            service,
+            transitive,
+            sparse_checkout,
+        } => files(&service, opts, transitive, sparse_checkout),
+        Operation::Fingerprint(service) => fingerprint(service.as_deref(), opts),
+        Operation::MarkBuilt {
+            service,
+            revision,
+            state,
+        } => mark_built(&service, &revision, &state, opts),
+        Operation::Run {
+            command,
+            jobs,
+            affected,
+        } => execute(std::io::stdin().lock(), opts, command, jobs, &affected),
+    }
+}
+
+/// Run the 'run' operation of monodeps.
+///
+/// It will determine all affected services exactly like the 'dependencies' operation (see
+/// `affected`) and execute the given `command` in every service directory in dependency order, at
+/// most `jobs` in parallel. Afterwards a summary of all services is printed and the process exits
+/// with a non-zero exit code if any command failed.
+///
+/// The fingerprint manifest of `--write-manifest` is only written if all commands succeeded, so
+/// that it always records the state of the last successful run.
+fn execute<R>(reader: R, opts: Opts, command: RunCommand, jobs: usize, options: &AffectedOptions)
+where
+    R: BufRead,
+{
+    let affected = match affected(reader, options, &opts) {
+        Ok(affected) => affected,
+        Err(err) => {
+            eprintln!("failed to resolve dependencies: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    let results = bail_out(run::run(affected.services, &command, jobs, &opts));
+
+    println!();
+    bail_out(run::summary(&results, &mut std::io::stdout().lock()));
+
+    if results.iter().any(|result| !result.status.is_ok()) {
+        std::process::exit(1);
+    }
+
+    bail_out(write_manifest(affected.manifest.as_ref(), options));
+}
+
+/// Run the 'mark-built' operation of monodeps.
+///
+/// It will record the commit of the given `revision` as the one the given service was last built
+/// from in the build state file `state`.
+fn mark_built(service: &str, revision: &str, state: &str, opts: Opts) {
+    let result = BuildState::load(state).and_then(|mut build_state| {
+        build_state.mark_built(service, revision, &opts)?;
+        build_state.write(state)
+    });
+
+    if let Err(err) = result {
+        eprintln!("failed to update build state {state}: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'lock' operation of monodeps.
+///
+/// It will write the discovered dependency graph into the lockfile of the target directory. With
+/// `check` the existing lockfile is compared against the discovered graph instead, printing all
+/// differences and exiting with a non-zero exit code on any drift.
+fn lockfile(opts: Opts, check: bool) {
+    let path = lock::path(&opts);
+    let services = bail_out(service::Service::discover(&opts));
+    let current = Lockfile::new(&services, &opts);
+
+    if !check {
+        if let Err(err) = current.write(&path) {
+            eprintln!("failed to write lockfile {}: {err}", path.display());
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let previous = bail_out(Lockfile::load(&path));
+    let drifts = previous.diff(&current);
+
+    if !drifts.is_empty() {
+        eprintln!(
+            "lockfile {} is out of date (run 'monodeps lock' to update):",
+            path.display()
+        );
+
+        for drift in drifts {
+            println!("{drift}");
+        }
+
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'watch' operation of monodeps.
+///
+/// It will watch the target directory for file changes and print the services that are affected
+/// by all changes since the start of the watch, whenever these change. The JSON output is
+/// written as JSON lines, meaning one JSON array per update.
+fn watch(opts: Opts) {
+    let result = watch::run(&opts, |services, discovered| {
+        bail_out(output::output(services, discovered, &[], &opts));
+        println!();
+    });
+
+    if let Err(err) = result {
+        eprintln!("failed to watch for changes: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'cache clean' operation of monodeps.
+///
+/// It will remove the discovery cache of the target directory.
+fn cache_clean(opts: Opts) {
+    if let Err(err) = cache::clean(&opts) {
+        eprintln!("failed to clean discovery cache: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'graph' operation of monodeps.
+///
+/// It will output a directed graph in dot digraph notation that can be used
+/// to render a graph visualizing the service dependencies. This dependency
+/// graph is not the 100% identical to the actual dependencies because it
+/// cannot emulate all possible file dependencies (especially ones that are
+/// based on patterns or regular expressions).
+///
+/// Dependencies that cannot be mapped to any service and global dependencies may be rendered as
+/// separate nodes via the given `options` (dot only).
+///
+/// With `--affected` the services affected by the files given via STDIN are highlighted.
+///
+/// With `diff` the graph is compared with the one at the given git revision instead.
+fn graph<R>(reader: R, opts: Opts, diff: Option<String>, options: GraphOptions)
+where
+    R: BufRead,
+{
+    let result = match diff {
+        Some(base) => graph::diff(&base, opts),
+        None => {
+            let changed_files = if options.affected {
+                Some(bail_out(collect_changed_files(reader)))
+            } else {
+                None
+            };
+            graph::calculate(opts, &options, changed_files)
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("failed to calculate service dependency graph: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'report' operation of monodeps.
+///
+/// It will write a self-contained HTML report of the service graph into the file `html`. If
+/// changed files are piped via STDIN, the affected services are included as well.
+fn report(html: &str, opts: Opts) {
+    let stdin = std::io::stdin();
+    let changed_files = if stdin.is_terminal() {
+        Vec::new()
+    } else {
+        bail_out(collect_changed_files(stdin.lock()))
+    };
+
+    if let Err(err) = report::write(html, &opts, changed_files) {
+        eprintln!("failed to write report: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'dependencies' (default) operation of monodeps.
+///
+/// It will discover all services in the given target directory and determine all affected
+/// services as selected by the given `options` (see `affected`). With `github_output` the affected
+/// services are written to the file given in `$GITHUB_OUTPUT` as well.
+fn dependencies<R>(reader: R, opts: Opts, options: &AffectedOptions, github_output: bool)
+where
+    R: BufRead,
+{
+    let affected = match affected(reader, options, &opts) {
+        Ok(affected) => affected,
+        Err(err) => {
+            eprintln!("failed to resolve dependencies: {err}");
+            std::process::exit(1)
+        }
+    };
+
+    bail_out(write_manifest(affected.manifest.as_ref(), options));
+
+    if github_output {
+        bail_out(output::write_github_output(&affected.services, &opts));
+    }
+    bail_out(output::output(
+        affected.services,
+        &affected.discovered,
+        &affected.changed_files,
+        &opts,
+    ))
+}
+
+/// Run the 'files' operation of monodeps.
+///
+/// It will output all files the given service depends on (its input closure), or the git
+/// sparse-checkout patterns that are required to check out these files.
+fn files(service: &str, opts: Opts, transitive: bool, sparse_checkout: bool) {
+    let mut stdout = std::io::stdout().lock();
+
+    if let Err(err) = closure::output(service, transitive, sparse_checkout, &opts, &mut stdout) {
+        eprintln!("failed to determine files of service: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'fingerprint' operation of monodeps.
+///
+/// It will output the content-hash fingerprint of the given service or of all services.
+fn fingerprint(service: Option<&str>, opts: Opts) {
+    if let Err(err) = fingerprint::output(service, &opts, &mut std::io::stdout().lock()) {
+        eprintln!("failed to determine fingerprint: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Run the 'validate' operation of monodeps.
+///
+/// It will discover a service in the given target directory and determine all services, folder and
+/// files that service is depending on.
+fn validate(service_path: &str, opts: Opts) {
+    match service::Service::try_determine(service_path, &opts) {
+        Ok(svc) => {
+            if !svc.depsfile.dependencies.is_empty() {
+                println!("Dependencies (configured):");
+
+                for dependency in svc.depsfile.dependencies {
+                    println!("  - {}", dependency);
+                }
+            }
+
+            if !svc.auto_dependencies.is_empty() {
+                println!("Dependencies (auto-discovered):");
+
+                for dependency in svc.auto_dependencies {
+                    println!("  - {} [{}]", dependency.pattern, dependency.language);
+                }
+            }
+
+            if !opts.config.global_dependencies.is_empty() {
+                println!("Dependencies (global):");
+
+                for dependency in opts.config.global_dependencies {
+                    println!("  - {}", dependency);
+                }
+            }
+        }
+        Err(err) => {
+            eprintln!("failed validate service dependencies: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The services selected by `affected`.
+struct Affected {
+    /// The affected services (of the current shard).
+    services: Vec<Service>,
+    /// All discovered services, only required for the fingerprints of the affected ones.
+    discovered: Vec<Service>,
+    /// The changed files read from STDIN.
+    changed_files: Vec<String>,
+    /// The fingerprint manifest of the current state, if any manifest option is given.
+    manifest: Option<Manifest>,
+}
+
+/// Determine the affected services of the target directory as selected by the given `options`.
+///
+/// By default these are the services affected by the files given via `reader` (STDIN), resolved
+/// against the union of the current and the `base` dependency graph if given. With `--all` all
+/// services are affected.
+///
+/// With `since_manifest` the affected services are the ones whose fingerprint differs from the
+/// given fingerprint manifest instead, without reading STDIN. The previous manifest is loaded
+/// here, before the current one is written (see `write_manifest`), so that both may refer to the
+/// same file.
+///
+/// With `since_built` every service is resolved against the changes since the commit it was last
+/// built from, as recorded in the given build state file, without reading STDIN.
+///
+/// With a `--shard` only the affected services of the given shard are returned.
+fn affected<R>(reader: R, options: &AffectedOptions, opts: &Opts) -> Result<Affected>
+where
+    R: BufRead,
+{
+    let services = Service::discover(opts)?;
+
+    // all services are only required for the fingerprints of the affected ones
+    let discovered = if opts.fingerprint {
+        services.clone()
+    } else {
+        Vec::new()
+    };
+
+    let manifest = if options.since_manifest.is_some() || options.write_manifest.is_some() {
+        Some(Manifest::new(&services, opts)?)
+    } else {
+        None
+    };
+
+    let changed_files =
+        if !opts.all && options.since_manifest.is_none() && options.since_built.is_none() {
+            collect_changed_files(reader)?
+        } else {
+            Vec::new()
+        };
+
+    let services = if let Some(path) = &options.since_manifest {
+        let previous = if Path::new(path).exists() {
+            Some(Manifest::load(path)?)
+        } else {
+            log::warn!("manifest {path} does not exist: all services are affected");
+            None
+        };
+
+        match &manifest {
+            Some(current) => current.changed(services, previous.as_ref(), opts),
+            None => services,
+        }
+    } else if let Some(state) = &options.since_built {
+        BuildState::load(state)?.affected(services, opts)?
+    } else if opts.all {
+        services
+    } else {
+        match &options.base {
+            Some(base) => {
+                let base_graph = match base {
+                    BaseGraph::Revision(revision) => Lockfile::at_revision(revision, opts)?,
+                    BaseGraph::Lockfile(path) => Lockfile::load(path)?,
+                };
+                dependency::resolve_with_base(services, changed_files.clone(), &base_graph, opts)?
+            }
+            None => dependency::resolve(services, changed_files.clone(), opts)?,
+        }
+    };
+
+    let services = match &opts.shard {
+        Some(shard) => shard.partition(services, opts),
+        None => services,
+    };
+
+    Ok(Affected {
+        services,
+        discovered,
+        changed_files,
+        manifest,
+    })
+}
+
+/// Write the given fingerprint `manifest` of the current state to the file of `--write-manifest`,
+/// if any.
+fn write_manifest(manifest: Option<&Manifest>, options: &AffectedOptions) -> Result<()> {
+    if let (Some(manifest), Some(path)) = (manifest, &options.write_manifest) {
+        manifest
+            .write(path)
+            .map_err(|err| anyhow!("failed to write manifest {path}: {err}"))?;
+    }
+
+    Ok(())
+}
+
+/// Write any error to STDERR and exit with return code 1.
+fn bail_out<T>(result: Result<T>) -> T {
+    match result {
+        Ok(inner) => inner,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Read the input of changed files from STDIN, expecting one file path per line.
+fn collect_changed_files<R>(reader: R) -> Result<Vec<String>>
+where
+    R: BufRead,
+{
+    let mut all = Vec::new();
+
+    for line in reader.lines() {
+        all.push(line?);
+    }
+
+    Ok(all)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::cli::{AffectedOptions, GraphOptions, Opts};
+    use crate::config::{AutoDiscoveryConfig, Config, DepPattern, DotnetConfig, GoDepsConfig};
+    use crate::path::PathInfo;
+    use crate::test_util::create_file;
+
+    use super::{affected, dependencies, graph, validate, write_manifest};
+
+    fn mk_opts() -> Opts {
+        Opts {
+            target: PathInfo::new("./tests", "").unwrap(),
+            config: Config {
+                auto_discovery: AutoDiscoveryConfig {
+                    go: GoDepsConfig {
+                        package_prefixes: vec![],
+                    },
+                    dotnet: DotnetConfig {
+                        package_namespaces: vec![],
+                    },
+                    plugins: vec![],
+                    references: vec![],
+                },
+                global_dependencies: vec![DepPattern::plain(".github", "./tests").unwrap()],
+            },
+            all: false,
+            output: crate::cli::OutputFormat::Plain,
+            verbose: true,
+            relative: false,
+            cache: false,
+            supported_roots: vec![],
+            pipeline_template: None,
+            format_template: None,
+            files: false,
+            fingerprint: false,
+            shard: None,
+            custom_analyzers: vec![],
+        }
+    }
+
+    #[test]
+    fn validate_does_not_fail() {
+        // we just test that it does not fail
+        validate("./examples/full/service-c", mk_opts());
+    }
+
+    #[test]
+    fn graph_does_not_fail() {
+        // we just test that it does not fail
+        graph(Cursor::new(""), mk_opts(), None, GraphOptions::default());
+    }
+
+    #[test]
+    fn test_dependencies() {
+        // we are emulating STDIN
+        let input = String::from("some/file\nanother file\n");
+        let cursor = Cursor::new(input);
+
+        // we just test that is does not fail
+        dependencies(cursor, mk_opts(), &AffectedOptions::default(), false);
+    }
+
+    #[test]
+    fn test_dependencies_json() {
+        // we are emulating STDIN
+        let input = String::from("some/file\nanother file\n");
+        let cursor = Cursor::new(input);
+        let opts = mk_opts();
+        let json_opts = Opts {
+            output: crate::cli::OutputFormat::Json,
+            ..opts
+        };
+
+        // we just test that is does not fail
+        dependencies(cursor, json_opts, &AffectedOptions::default(), false);
+    }
+
+    #[test]
+    fn test_dependencies_yaml() {
+        // we are emulating STDIN
+        let input = String::from("some/file\nanother file\n");
+        let cursor = Cursor::new(input);
+        let opts = mk_opts();
+        let yaml_opts = Opts {
+            output: crate::cli::OutputFormat::Yaml,
+            ..opts
+        };
+
+        // we just test that is does not fail
+        dependencies(cursor, yaml_opts, &AffectedOptions::default(), false);
+    }
+
+    #[test]
+    fn test_dependencies_manifest() {
+        let dir = tempfile::Builder::default()
+            .prefix("mdtest")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("manifest.json").display().to_string();
+
+        // the first run writes the manifest, the second compares with it
+        let write = AffectedOptions {
+            write_manifest: Some(path.clone()),
+            ..Default::default()
+        };
+        dependencies(Cursor::new(""), mk_opts(), &write, false);
+
+        let since = AffectedOptions {
+            since_manifest: Some(path.clone()),
+            ..Default::default()
+        };
+        dependencies(Cursor::new(""), mk_opts(), &since, false);
+
+        assert!(std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn manifests_same_file() -> anyhow::Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "lib/a.go", "package lib\n")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let path = dir.path().join("manifest.json").display().to_string();
+
+        let options = AffectedOptions {
+            since_manifest: Some(path.clone()),
+            write_manifest: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let changed = |opts: &Opts| -> anyhow::Result<Vec<String>> {
+            let affected = affected(Cursor::new(""), &options, opts)?;
+            write_manifest(affected.manifest.as_ref(), &options)?;
+
+            let mut changed: Vec<_> = affected
+                .services
+                .iter()
+                .map(|svc| svc.path.relative_to(&opts.target))
+                .collect();
+            changed.sort();
+            Ok(changed)
+        };
+
+        // without any previous manifest all services are affected
+        assert_eq!(vec!["./api", "./lib", "./web"], changed(&opts)?);
+        assert!(changed(&opts)?.is_empty());
+
+        create_file(dir.path(), "lib/a.go", "package lib\n\nfunc A() {}\n")?;
+        assert_eq!(vec!["./api", "./lib"], changed(&opts)?);
+        assert!(changed(&opts)?.is_empty());
+
+        Ok(())
+    }
+}
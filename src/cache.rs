@@ -0,0 +1,570 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cli::Opts;
+use crate::config::{Depsfile, DepsfileType};
+use crate::path::PathInfo;
+use crate::service::{AutoDependency, Service, input_files, parents_until_root};
+
+/// Name of the cache directory that is created in the target directory.
+pub const CACHE_DIR: &str = ".monodeps-cache";
+
+const CACHE_FILE: &str = "discovery.json";
+
+/// Bump this version whenever the cache layout or the discovery logic changes in a way that
+/// previously cached results must not be reused anymore.
+const CACHE_VERSION: u32 = 2;
+
+/// Fingerprint of a single input file of the service discovery.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileFingerprint {
+    modified: u64,
+    size: u64,
+    hash: String,
+}
+
+/// Cached discovery result of a single service alongside all input files that were considered
+/// relevant for the result.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    filetype: DepsfileType,
+    depsfile: String,
+    inputs: BTreeMap<String, FileFingerprint>,
+    depsfile_content: Depsfile,
+    auto_dependencies: Vec<AutoDependency>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheContent {
+    version: u32,
+    config: String,
+    services: HashMap<String, CacheEntry>,
+}
+
+/// The `DiscoveryCache` persists per-service discovery results in the `.monodeps-cache`
+/// directory of the target directory. Cached results are reused as long as the files the
+/// discovery is based on are unchanged. Any change of the global configuration (or the monodeps
+/// version) invalidates the cache as a whole.
+///
+/// The set of relevant input files is determined on a "best effort" base: all files of the
+/// service directory, all files directly in any parent directory up to the repository root and
+/// all files referenced by auto-discovered dependencies.
+pub struct DiscoveryCache {
+    location: Option<PathBuf>,
+    content: CacheContent,
+    seen: HashSet<String>,
+    dirty: bool,
+}
+
+impl DiscoveryCache {
+    /// Load the discovery cache of the target directory given via `Opts`. In case the cache is
+    /// disabled, a no-op cache is returned.
+    pub fn load(opts: &Opts) -> Self {
+        let config = config_key(opts);
+        let location = cache_location(&opts.target);
+
+        if !opts.cache || !Path::new(&opts.target.canonicalized).is_dir() {
+            return Self::disabled(config);
+        }
+
+        let content = match read_cache(&location) {
+            Ok(Some(content)) if content.version == CACHE_VERSION && content.config == config => {
+                content
+            }
+            Ok(_) => CacheContent {
+                version: CACHE_VERSION,
+                config,
+                services: HashMap::new(),
+            },
+            Err(err) => {
+                log::warn!("failed to read discovery cache: {err}");
+                CacheContent {
+                    version: CACHE_VERSION,
+                    config,
+                    services: HashMap::new(),
+                }
+            }
+        };
+
+        Self {
+            location: Some(location),
+            content,
+            seen: HashSet::new(),
+            dirty: false,
+        }
+    }
+
+    fn disabled(config: String) -> Self {
+        Self {
+            location: None,
+            content: CacheContent {
+                version: CACHE_VERSION,
+                config,
+                services: HashMap::new(),
+            },
+            seen: HashSet::new(),
+            dirty: false,
+        }
+    }
+
+    /// Try to find a cached `Service` for the given service and dependency file locations. The
+    /// cached service is only returned if none of its input files changed since it was stored.
+    pub fn lookup(
+        &mut self,
+        service_location: &PathInfo,
+        depsfile_location: &PathInfo,
+        filetype: DepsfileType,
+        opts: &Opts,
+    ) -> Option<Service> {
+        self.location.as_ref()?;
+        self.seen.insert(service_location.canonicalized.clone());
+
+        let entry = self
+            .content
+            .services
+            .get_mut(&service_location.canonicalized)?;
+        if entry.filetype != filetype || entry.depsfile != depsfile_location.canonicalized {
+            return None;
+        }
+
+        let auto_dep_files = auto_dependency_files(&entry.auto_dependencies);
+        let current_inputs = collect_inputs(service_location, &auto_dep_files, opts);
+
+        if current_inputs.len() != entry.inputs.len() {
+            return None;
+        }
+
+        let mut refreshed = false;
+
+        for (file, (modified, size)) in current_inputs {
+            let cached = entry.inputs.get_mut(&file)?;
+            if cached.modified == modified && cached.size == size {
+                continue;
+            }
+
+            // the file metadata changed, but maybe the content did not
+            let hash = content_hash(&file).ok()?;
+            if cached.hash != hash {
+                return None;
+            }
+
+            cached.modified = modified;
+            cached.size = size;
+            refreshed = true;
+        }
+
+        self.dirty |= refreshed;
+
+        log::debug!(
+            "cache: reusing discovery of '{}'",
+            service_location.display_path
+        );
+
+        Some(Service {
+            path: service_location.clone(),
+            depsfile: entry.depsfile_content.clone(),
+            auto_dependencies: entry.auto_dependencies.clone(),
+            trigger: None,
+            changed_files: Vec::new(),
+            dependency_files: Vec::new(),
+        })
+    }
+
+    /// Store the discovery result of the given `Service` in the cache.
+    pub fn store(
+        &mut self,
+        service: &Service,
+        depsfile_location: &PathInfo,
+        filetype: DepsfileType,
+        opts: &Opts,
+    ) {
+        if self.location.is_none() {
+            return;
+        }
+
+        let auto_dep_files = auto_dependency_files(&service.auto_dependencies);
+        let mut inputs = BTreeMap::new();
+
+        for (file, (modified, size)) in collect_inputs(&service.path, &auto_dep_files, opts) {
+            let hash = match content_hash(&file) {
+                Ok(hash) => hash,
+                // we cannot reliably cache anything we cannot read
+                Err(_) => return,
+            };
+
+            inputs.insert(
+                file,
+                FileFingerprint {
+                    modified,
+                    size,
+                    hash,
+                },
+            );
+        }
+
+        self.seen.insert(service.path.canonicalized.clone());
+        self.content.services.insert(
+            service.path.canonicalized.clone(),
+            CacheEntry {
+                filetype,
+                depsfile: depsfile_location.canonicalized.clone(),
+                inputs,
+                depsfile_content: service.depsfile.clone(),
+                auto_dependencies: service.auto_dependencies.clone(),
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Write the cache to disk, if anything changed. Services that were not part of the current
+    /// discovery are removed from the cache.
+    pub fn persist(mut self) -> Result<()> {
+        let location = match &self.location {
+            Some(location) => location,
+            None => return Ok(()),
+        };
+
+        let num_cached = self.content.services.len();
+        self.content
+            .services
+            .retain(|service, _| self.seen.contains(service));
+
+        if !self.dirty && num_cached == self.content.services.len() {
+            return Ok(());
+        }
+
+        if let Some(dir) = location.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let file = File::create(location)?;
+        serde_json::to_writer(file, &self.content)?;
+
+        Ok(())
+    }
+}
+
+/// Remove the discovery cache of the target directory given via `Opts`.
+pub fn clean(opts: &Opts) -> Result<()> {
+    let dir = PathBuf::from(&opts.target.canonicalized).join(CACHE_DIR);
+
+    if dir.is_dir() {
+        std::fs::remove_dir_all(dir)?;
+    }
+
+    Ok(())
+}
+
+fn cache_location(target: &PathInfo) -> PathBuf {
+    PathBuf::from(&target.canonicalized)
+        .join(CACHE_DIR)
+        .join(CACHE_FILE)
+}
+
+fn read_cache(location: &Path) -> Result<Option<CacheContent>> {
+    if !location.is_file() {
+        return Ok(None);
+    }
+
+    let reader = BufReader::new(File::open(location)?);
+    Ok(Some(serde_json::from_reader(reader)?))
+}
+
+/// Determine the key of the "global" configuration. Whenever this changes, all cached entries
+/// are invalidated.
+fn config_key(opts: &Opts) -> String {
+    let mut hasher = Sha256::new();
+
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update(format!("{:?}", opts.config));
+    hasher.update(format!("{:?}", opts.supported_roots));
+
+    for custom in &opts.custom_analyzers {
+        hasher.update(&custom.name);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Calculate the SHA-256 hash of the content of the given file.
+pub(crate) fn content_hash<P>(file: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let mut hasher = Sha256::new();
+    let mut reader = BufReader::new(File::open(file)?);
+
+    std::io::copy(&mut reader, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn auto_dependency_files(auto_dependencies: &[AutoDependency]) -> Vec<String> {
+    auto_dependencies
+        .iter()
+        .flat_map(|auto_dep| auto_dep.pattern.hash())
+        .filter(|path| Path::new(path).is_file())
+        .map(|path| path.to_owned())
+        .collect()
+}
+
+/// The locations of all input files that are considered relevant for the discovery of services
+/// (see `DiscoveryCache`): all files of the service directories, all files directly in any of
+/// their parent directories up to the repository root and all files referenced by
+/// auto-discovered dependencies.
+#[derive(Debug, Default)]
+pub(crate) struct DiscoveryInputs {
+    service_dirs: BTreeSet<PathBuf>,
+    parent_dirs: BTreeSet<PathBuf>,
+    files: BTreeSet<PathBuf>,
+}
+
+impl DiscoveryInputs {
+    /// The discovery inputs of all given (discovered) services.
+    pub(crate) fn new(services: &[Service], opts: &Opts) -> Self {
+        let mut inputs = Self::default();
+
+        for service in services {
+            let auto_dep_files = auto_dependency_files(&service.auto_dependencies);
+            inputs.add_service(&service.path, &auto_dep_files, opts);
+        }
+
+        inputs
+    }
+
+    fn add_service(&mut self, service_dir: &PathInfo, auto_dep_files: &[String], opts: &Opts) {
+        self.service_dirs
+            .insert(PathBuf::from(&service_dir.canonicalized));
+        self.parent_dirs.extend(
+            parents_until_root(&service_dir.canonicalized, &opts.target)
+                .into_iter()
+                .skip(1),
+        );
+        self.files.extend(auto_dep_files.iter().map(PathBuf::from));
+    }
+
+    /// Whether the file at the given (canonicalized) `path` is an input of the discovery. The
+    /// file does not need to exist (anymore).
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.files.contains(path)
+            || path
+                .parent()
+                .is_some_and(|parent| self.parent_dirs.contains(parent))
+            || self.service_dirs.iter().any(|dir| path.starts_with(dir))
+    }
+
+    /// All existing input files alongside their modification time and size.
+    fn collect(&self) -> BTreeMap<String, (u64, u64)> {
+        let mut inputs = BTreeMap::new();
+
+        let mut add_input = |path: &Path| {
+            if let (Some(file), Some(metadata)) = (path.to_str(), file_metadata(path)) {
+                inputs.insert(file.to_owned(), metadata);
+            }
+        };
+
+        // all files of the service directories themselves (including hidden ones that may be
+        // relevant to custom analyzers)
+        for dir in &self.service_dirs {
+            for entry in input_files(dir) {
+                if entry.file_type().is_file() {
+                    add_input(entry.path());
+                }
+            }
+        }
+
+        // files in parent directories (e.g. directory configuration files or workspace files)
+        for parent in &self.parent_dirs {
+            let entries = match std::fs::read_dir(parent) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+                    add_input(&entry.path());
+                }
+            }
+        }
+
+        // files referenced by auto-discovered dependencies
+        for file in &self.files {
+            add_input(file);
+        }
+
+        inputs
+    }
+}
+
+/// Collect all input files (alongside their modification time and size) that are considered
+/// relevant for the discovery of the service at `service_dir`.
+fn collect_inputs(
+    service_dir: &PathInfo,
+    auto_dep_files: &[String],
+    opts: &Opts,
+) -> BTreeMap<String, (u64, u64)> {
+    let mut inputs = DiscoveryInputs::default();
+    inputs.add_service(service_dir, auto_dep_files, opts);
+    inputs.collect()
+}
+
+fn file_metadata(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+
+    Some((modified, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::cli::Opts;
+    use crate::config::{Config, DepsfileType};
+    use crate::path::PathInfo;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{CACHE_DIR, DiscoveryCache, clean};
+
+    fn tmp() -> Result<TempDir> {
+        Ok(tempfile::Builder::default().prefix("mdtest").tempdir()?)
+    }
+
+    fn mk_opts(dir: &TempDir) -> Result<Opts> {
+        Ok(Opts {
+            target: PathInfo::new(dir.path(), "")?,
+            config: Config::default(),
+            output: crate::cli::OutputFormat::Plain,
+            verbose: false,
+            all: false,
+            relative: false,
+            cache: true,
+            supported_roots: vec![],
+            pipeline_template: None,
+            format_template: None,
+            files: false,
+            fingerprint: false,
+            shard: None,
+            custom_analyzers: vec![],
+        })
+    }
+
+    fn lookup(cache: &mut DiscoveryCache, dir: &TempDir, opts: &Opts) -> Result<Option<Service>> {
+        let service = PathInfo::new("service", dir.path())?;
+        let depsfile = PathInfo::new("service/Depsfile", dir.path())?;
+
+        Ok(cache.lookup(&service, &depsfile, DepsfileType::Depsfile, opts))
+    }
+
+    #[test]
+    fn cache_reuses_unchanged_service() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "service/Depsfile",
+            "dependencies:\n  - ../shared\n",
+        )?;
+
+        let opts = mk_opts(&dir)?;
+        let services = Service::discover(&opts)?;
+        assert_eq!(1, services.len());
+        assert!(dir.path().join(CACHE_DIR).is_dir());
+
+        let mut cache = DiscoveryCache::load(&opts);
+        let cached = lookup(&mut cache, &dir, &opts)?;
+
+        assert!(cached.is_some());
+        assert_eq!(1, cached.unwrap().depsfile.dependencies.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_invalidates_changed_service() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "service/Depsfile",
+            "dependencies:\n  - ../shared\n",
+        )?;
+
+        let opts = mk_opts(&dir)?;
+        Service::discover(&opts)?;
+
+        create_file(
+            dir.path(),
+            "service/Depsfile",
+            "dependencies:\n  - ../shared\n  - ../other\n",
+        )?;
+
+        let mut cache = DiscoveryCache::load(&opts);
+        assert!(lookup(&mut cache, &dir, &opts)?.is_none());
+
+        // the next discovery picks up the changed dependencies
+        let services = Service::discover(&opts)?;
+        assert_eq!(2, services[0].depsfile.dependencies.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_invalidates_new_file() -> Result<()> {
+        let dir = tmp()?;
+        create_file(dir.path(), "service/Depsfile", "")?;
+
+        let opts = mk_opts(&dir)?;
+        Service::discover(&opts)?;
+
+        create_file(dir.path(), "service/main.go", "package main\n")?;
+
+        let mut cache = DiscoveryCache::load(&opts);
+        assert!(lookup(&mut cache, &dir, &opts)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_disabled() -> Result<()> {
+        let dir = tmp()?;
+        create_file(dir.path(), "service/Depsfile", "")?;
+
+        let opts = Opts {
+            cache: false,
+            ..mk_opts(&dir)?
+        };
+        Service::discover(&opts)?;
+
+        assert!(!dir.path().join(CACHE_DIR).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_clean() -> Result<()> {
+        let dir = tmp()?;
+        create_file(dir.path(), "service/Depsfile", "")?;
+
+        let opts = mk_opts(&dir)?;
+        Service::discover(&opts)?;
+        assert!(dir.path().join(CACHE_DIR).is_dir());
+
+        clean(&opts)?;
+        assert!(!dir.path().join(CACHE_DIR).exists());
+
+        Ok(())
+    }
+}
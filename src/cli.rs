@@ -1,39 +1,313 @@
+use std::path::Path;
+use std::sync::Arc;
+
 use anyhow::{Result, anyhow, bail};
-use getopts::Options;
+use getopts::{Matches, Options};
 
 use crate::config::{Config, DepsfileType};
 use crate::path::PathInfo;
-
-#[derive(Debug, PartialEq)]
+use crate::run::RunCommand;
+use crate::service::{CustomAnalyzer, LanguageAnalyzer};
+use crate::shard::{Shard, ShardWeight};
+use crate::state;
+use crate::template::Template;
+
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum OutputFormat {
     Plain,
     Json,
     Yaml,
+    Dot,
+    Mermaid,
+    PlantUml,
+    GraphMl,
+    GitHubMatrix,
+    GitLab,
+    Buildkite,
+    Markdown,
+    /// User-defined output template (see `--format-template` and `--template-file`).
+    Template,
+}
+
+impl OutputFormat {
+    /// Whether the format is a CI pipeline rendered from a template (see `--pipeline-template`).
+    pub fn is_pipeline(&self) -> bool {
+        matches!(self, OutputFormat::GitLab | OutputFormat::Buildkite)
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Plain => f.write_str("plain"),
+            OutputFormat::Json => f.write_str("json"),
+            OutputFormat::Yaml => f.write_str("yaml"),
+            OutputFormat::Dot => f.write_str("dot"),
+            OutputFormat::Mermaid => f.write_str("mermaid"),
+            OutputFormat::PlantUml => f.write_str("plantuml"),
+            OutputFormat::GraphMl => f.write_str("graphml"),
+            OutputFormat::GitHubMatrix => f.write_str("github-matrix"),
+            OutputFormat::GitLab => f.write_str("gitlab"),
+            OutputFormat::Buildkite => f.write_str("buildkite"),
+            OutputFormat::Markdown => f.write_str("markdown"),
+            OutputFormat::Template => f.write_str("template"),
+        }
+    }
+}
+
+/// The source of the base dependency graph that is used in addition to the current one.
+#[derive(Debug, PartialEq, Clone)]
+pub enum BaseGraph {
+    /// Discover the services at the given git revision.
+    Revision(String),
+    /// Load the dependency graph from the given lockfile.
+    Lockfile(String),
+}
+
+/// Direction in which the graph is traversed starting from the focused service.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// Services that depend on the focused service.
+    Up,
+    /// Services the focused service depends on.
+    Down,
+    #[default]
+    Both,
+}
+
+/// Options that select the affected services of the 'dependencies' and 'run' operations.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct AffectedOptions {
+    /// Resolve the changed files against the union of the current and this base graph.
+    pub base: Option<BaseGraph>,
+    /// Determine the affected services by comparing with the given fingerprint manifest instead
+    /// of the changed files given via STDIN.
+    pub since_manifest: Option<String>,
+    /// Write the fingerprint manifest of the current state to the given file.
+    pub write_manifest: Option<String>,
+    /// Determine the affected services by the changes since the commit every service was last
+    /// built from, as recorded in the given build state file, instead of STDIN.
+    pub since_built: Option<String>,
+}
+
+/// Options that control which nodes are rendered by the 'graph' operation.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct GraphOptions {
+    /// Render file, glob and regex dependencies that do not point into any service as nodes.
+    pub pattern_nodes: bool,
+    /// Render the global dependencies of the configuration as nodes.
+    pub global_nodes: bool,
+    /// Render the neighbourhood of the given service only.
+    pub focus: Option<String>,
+    /// Maximum distance of services to the focused service (unlimited if not specified).
+    pub depth: Option<usize>,
+    pub direction: Direction,
+    /// Highlight the services that are affected by the changed files given via STDIN.
+    pub affected: bool,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Operation {
-    Dependencies,
+    Dependencies {
+        affected: AffectedOptions,
+        /// Write the affected services to the file given in `$GITHUB_OUTPUT` as well.
+        github_output: bool,
+    },
     Validate(String),
-    Graph,
+    Graph {
+        diff: Option<String>,
+        options: GraphOptions,
+    },
+    CacheClean,
+    Watch,
+    Lock {
+        check: bool,
+    },
+    Report {
+        html: String,
+    },
+    /// List all files the given service depends on (its input closure).
+    Files {
+        service: String,
+        /// Include the files of all services the service (transitively) depends on.
+        transitive: bool,
+        /// Print git sparse-checkout patterns (cone mode) instead of the files.
+        sparse_checkout: bool,
+    },
+    /// Print the content-hash fingerprint of the given service or of all services.
+    Fingerprint(Option<String>),
+    /// Record the commit the given service was last built from in the build state file.
+    MarkBuilt {
+        service: String,
+        revision: String,
+        state: String,
+    },
+    /// Execute a command in every affected service directory in dependency order.
+    Run {
+        command: RunCommand,
+        /// Maximum number of commands that are executed in parallel.
+        jobs: usize,
+        affected: AffectedOptions,
+    },
+}
+
+impl Operation {
+    /// Name of the operation as given on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Dependencies { .. } => "dependencies",
+            Operation::Validate(_) => "validate",
+            Operation::Graph { diff: None, .. } => "graph",
+            Operation::Graph { diff: Some(_), .. } => "graph --diff",
+            Operation::CacheClean => "cache clean",
+            Operation::Watch => "watch",
+            Operation::Lock { .. } => "lock",
+            Operation::Report { .. } => "report",
+            Operation::Files { .. } => "files",
+            Operation::Fingerprint(_) => "fingerprint",
+            Operation::MarkBuilt { .. } => "mark-built",
+            Operation::Run { .. } => "run",
+        }
+    }
+
+    /// All output formats that are supported by the operation.
+    pub fn output_formats(&self) -> &'static [OutputFormat] {
+        match self {
+            Operation::Dependencies { .. } => &[
+                OutputFormat::Plain,
+                OutputFormat::Json,
+                OutputFormat::Yaml,
+                OutputFormat::GitHubMatrix,
+                OutputFormat::GitLab,
+                OutputFormat::Buildkite,
+                OutputFormat::Markdown,
+                OutputFormat::Template,
+            ],
+            // plain output of the graph is the dot notation
+            Operation::Graph { diff: None, .. } => &[
+                OutputFormat::Plain,
+                OutputFormat::Dot,
+                OutputFormat::Mermaid,
+                OutputFormat::PlantUml,
+                OutputFormat::Json,
+                OutputFormat::GraphMl,
+            ],
+            Operation::Graph { diff: Some(_), .. } => &[OutputFormat::Plain, OutputFormat::Dot],
+            Operation::Files { .. } | Operation::Fingerprint(_) => {
+                &[OutputFormat::Plain, OutputFormat::Json]
+            }
+            Operation::Watch => &[
+                OutputFormat::Plain,
+                OutputFormat::Json,
+                OutputFormat::Template,
+            ],
+            Operation::Validate(_)
+            | Operation::CacheClean
+            | Operation::Lock { .. }
+            | Operation::Report { .. }
+            | Operation::MarkBuilt { .. }
+            | Operation::Run { .. } => &[OutputFormat::Plain],
+        }
+    }
 }
 
+/// Options that control service discovery, dependency resolution and output of monodeps.
+///
+/// Usually these are parsed from the command line arguments (see `Opts::parse`). When using
+/// monodeps as a library, the options can be constructed programmatically via `Opts::new`
+/// instead.
+#[non_exhaustive]
 pub struct Opts {
     pub target: PathInfo,
     pub config: Config,
-    pub output: OutputFormat,
+    pub(crate) output: OutputFormat,
     pub verbose: bool,
     pub relative: bool,
     pub all: bool,
+    /// Include the changed files of every service in the output (see `--files`).
+    pub files: bool,
+    /// Include the fingerprint of every service in the output (see `--fingerprint`).
+    pub fingerprint: bool,
+    /// Output the affected services of the given shard only (see `--shard`).
+    pub(crate) shard: Option<Shard>,
+    pub cache: bool,
     pub supported_roots: Vec<DepsfileType>,
+    /// Template file of the CI pipeline output formats (e.g. `-o gitlab`).
+    pub pipeline_template: Option<String>,
+    /// Template of the `OutputFormat::Template` output format.
+    pub(crate) format_template: Option<Template>,
+    pub(crate) custom_analyzers: Vec<CustomAnalyzer>,
 }
 
 impl Opts {
-    pub fn parse() -> Result<(Operation, Self)> {
+    /// Create new `Opts` for the given `target` directory (relative to the current working
+    /// directory) and `Config` using the default settings otherwise. In contrast to the command
+    /// line usage, the discovery cache is disabled by default.
+    pub fn new<P>(target: P, config: Config) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            target: PathInfo::new(target, "")?,
+            config,
+            output: OutputFormat::Plain,
+            verbose: false,
+            relative: false,
+            all: false,
+            files: false,
+            fingerprint: false,
+            shard: None,
+            cache: false,
+            supported_roots: Vec::new(),
+            pipeline_template: None,
+            format_template: None,
+            custom_analyzers: Vec::new(),
+        })
+    }
+
+    /// Register a custom `LanguageAnalyzer` under the given `name`. The analyzer is invoked for
+    /// every service that contains any relevant file (see `LanguageAnalyzer::file_relevant`).
+    pub fn register_analyzer<A>(&mut self, name: &str, analyzer: A)
+    where
+        A: LanguageAnalyzer + Send + Sync + 'static,
+    {
+        self.custom_analyzers.push(CustomAnalyzer {
+            name: name.to_owned(),
+            analyzer: Arc::new(analyzer),
+        });
+    }
+
+    /// Parse the `Operation` and `Opts` from the process' command line arguments.
+    pub(crate) fn parse() -> Result<(Operation, Self)> {
         let args: Vec<_> = std::env::args().collect();
         Opts::parse_args(args)
     }
 
+    /// Create a copy of these options operating on another `target` directory. The discovery
+    /// cache is disabled for the copy.
+    pub fn for_target<P>(&self, target: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            target: PathInfo::new(target, "")?,
+            config: self.config.clone(),
+            output: self.output.clone(),
+            verbose: self.verbose,
+            relative: self.relative,
+            all: self.all,
+            files: self.files,
+            fingerprint: self.fingerprint,
+            shard: self.shard.clone(),
+            cache: false,
+            supported_roots: self.supported_roots.clone(),
+            pipeline_template: self.pipeline_template.clone(),
+            format_template: self.format_template.clone(),
+            custom_analyzers: self.custom_analyzers.clone(),
+        })
+    }
+
     pub fn is_supported(&self, filetype: &DepsfileType) -> bool {
         *filetype == DepsfileType::Depsfile || self.supported_roots.contains(filetype)
     }
@@ -42,16 +316,180 @@ impl Opts {
         let mut opts = Options::new();
         opts.optopt("t", "target", "target directory to operate on", "DIR");
         opts.optopt("c", "config", "configuration file", "FILE");
-        opts.optopt("o", "output", "output format [plain, yaml, json]", "FORMAT");
+        opts.optopt(
+            "o",
+            "output",
+            "output format [plain, yaml, json, dot, mermaid, plantuml, graphml, github-matrix, gitlab, buildkite, markdown]",
+            "FORMAT",
+        );
+        opts.optopt(
+            "",
+            "base",
+            "dependencies/run: also resolve against the graph at the given git revision",
+            "REV",
+        );
+        opts.optopt(
+            "",
+            "base-lockfile",
+            "dependencies/run: also resolve against the graph of the given lockfile",
+            "FILE",
+        );
+        opts.optopt(
+            "",
+            "since-manifest",
+            "dependencies/run: compare with the fingerprints of the given manifest instead of STDIN",
+            "FILE",
+        );
+        opts.optopt(
+            "",
+            "write-manifest",
+            "dependencies/run: write the fingerprint manifest of the current state",
+            "FILE",
+        );
+        opts.optopt(
+            "",
+            "shard",
+            "dependencies/run: output the affected services of the given shard only",
+            "i/N",
+        );
+        opts.optopt(
+            "",
+            "shard-weight",
+            "weight of services without a Depsfile 'weight' (default: depsfile)",
+            "depsfile|files",
+        );
+        opts.optflag(
+            "",
+            "topological",
+            "shard the services in topological order (dependencies in the same or earlier shards)",
+        );
+        opts.optflag(
+            "",
+            "since-built",
+            "dependencies/run: use the changes since every service was last built instead of STDIN",
+        );
+        opts.optopt(
+            "",
+            "state",
+            "build state file of '--since-built' and 'mark-built' (default: monodeps-state.json)",
+            "FILE",
+        );
+        opts.optopt(
+            "",
+            "diff",
+            "graph: compare with the given git revision",
+            "REV",
+        );
+        opts.optopt(
+            "",
+            "html",
+            "report: write a self-contained HTML report",
+            "FILE",
+        );
+        opts.optopt(
+            "",
+            "focus",
+            "graph: only render the neighbourhood of the given service",
+            "SERVICE",
+        );
+        opts.optopt(
+            "",
+            "depth",
+            "graph: maximum distance to the focused service",
+            "N",
+        );
+        opts.optopt(
+            "",
+            "direction",
+            "graph: direction starting from the focused service [up, down, both]",
+            "DIR",
+        );
+        opts.optopt(
+            "",
+            "pipeline-template",
+            "job template of the CI pipeline output formats",
+            "FILE",
+        );
+        opts.optopt(
+            "",
+            "format-template",
+            "output every service using the given template",
+            "TEMPLATE",
+        );
+        opts.optopt(
+            "",
+            "template-file",
+            "output every service using the template of the given file",
+            "FILE",
+        );
         opts.optflag("", "makefile", "accept 'Makefile' as project roots");
         opts.optflag("", "justfile", "accept 'justfile' as project roots");
         opts.optflag("", "buildfile", "accept 'Buildfile.yaml' as project roots");
         opts.optflag("", "relative", "return relative paths");
         opts.optflag("", "all", "return all discovered services");
+        opts.optflag(
+            "",
+            "files",
+            "include the changed files of every service [plain, json, yaml]",
+        );
+        opts.optflag("", "no-cache", "do not use the discovery cache");
+        opts.optflag(
+            "",
+            "check",
+            "lock: fail if the lockfile differs from the discovered graph",
+        );
+        opts.optflag(
+            "",
+            "fingerprint",
+            "dependencies: include the fingerprint of every service [json, yaml]",
+        );
+        opts.optflag(
+            "",
+            "transitive",
+            "files: include the files of all services the service depends on",
+        );
+        opts.optflag(
+            "",
+            "sparse-checkout",
+            "files: print git sparse-checkout patterns (cone mode)",
+        );
+        opts.optflag(
+            "",
+            "pattern-nodes",
+            "graph: render dependencies outside of any service as nodes (dot only)",
+        );
+        opts.optflag(
+            "",
+            "global-nodes",
+            "graph: render global dependencies as nodes (dot only)",
+        );
+        opts.optflag(
+            "",
+            "affected",
+            "graph: highlight services affected by the files given on STDIN",
+        );
+        opts.optflag(
+            "",
+            "github-output",
+            "dependencies: write the affected services to $GITHUB_OUTPUT",
+        );
         opts.optflag("v", "verbose", "verbose output");
         opts.optflag("", "version", "print version");
         opts.optflag("h", "help", "show help");
 
+        opts.optopt(
+            "",
+            "jobs",
+            "run: maximum number of commands executed in parallel (default: number of CPUs)",
+            "N",
+        );
+
+        // everything after '--' is the command of 'run'
+        let (args, command) = match args.iter().position(|arg| arg == "--") {
+            Some(pos) => (args[..pos].to_vec(), Some(args[pos + 1..].to_vec())),
+            None => (args, None),
+        };
+
         let matches = opts.parse(&args[1..])?;
 
         // print help/usage
@@ -66,6 +504,42 @@ impl Opts {
             std::process::exit(0);
         }
 
+        let github_output = matches.opt_present("github-output");
+        let base = match (matches.opt_str("base"), matches.opt_str("base-lockfile")) {
+            (Some(_), Some(_)) => bail!("only one of '--base' and '--base-lockfile' is allowed"),
+            (Some(revision), None) => Some(BaseGraph::Revision(revision)),
+            (None, Some(lockfile)) => Some(BaseGraph::Lockfile(lockfile)),
+            (None, None) => None,
+        };
+
+        let since_manifest = matches.opt_str("since-manifest");
+        if since_manifest.is_some() && (base.is_some() || matches.opt_present("all")) {
+            bail!(
+                "'--since-manifest' cannot be combined with '--base', '--base-lockfile' or '--all'"
+            );
+        }
+
+        let target_dir = matches.opt_str("t").unwrap_or(".".to_owned());
+        let state = state::path(&target_dir, matches.opt_str("state"));
+        let since_built = matches.opt_present("since-built");
+        if since_built && (base.is_some() || since_manifest.is_some() || matches.opt_present("all"))
+        {
+            bail!(
+                "'--since-built' cannot be combined with '--base', '--base-lockfile', '--since-manifest' or '--all'"
+            );
+        }
+
+        let affected = AffectedOptions {
+            base,
+            since_manifest,
+            write_manifest: matches.opt_str("write-manifest"),
+            since_built: since_built.then(|| state.clone()),
+        };
+        let dependencies = Operation::Dependencies {
+            affected: affected.clone(),
+            github_output,
+        };
+
         let operation = matches
             .free
             .first()
@@ -76,24 +550,116 @@ impl Opts {
                     }
                     Ok(Operation::Validate(matches.free[1].clone()))
                 }
-                "dependencies" => Ok(Operation::Dependencies),
-                "graph" => Ok(Operation::Graph),
+                "dependencies" => Ok(dependencies.clone()),
+                "graph" => Ok(Operation::Graph {
+                    diff: matches.opt_str("diff"),
+                    options: graph_options(&matches)?,
+                }),
+                "watch" => Ok(Operation::Watch),
+                "lock" => Ok(Operation::Lock {
+                    check: matches.opt_present("check"),
+                }),
+                "report" => match matches.opt_str("html") {
+                    Some(html) => Ok(Operation::Report { html }),
+                    None => bail!("missing '--html FILE' for 'report'"),
+                },
+                "files" => {
+                    if matches.free.len() != 2 {
+                        bail!("missing service for 'files'");
+                    }
+                    Ok(Operation::Files {
+                        service: matches.free[1].clone(),
+                        transitive: matches.opt_present("transitive"),
+                        sparse_checkout: matches.opt_present("sparse-checkout"),
+                    })
+                }
+                "fingerprint" => Ok(Operation::Fingerprint(matches.free.get(1).cloned())),
+                "mark-built" => {
+                    if matches.free.len() != 3 {
+                        bail!("missing service or revision for 'mark-built'");
+                    }
+                    Ok(Operation::MarkBuilt {
+                        service: matches.free[1].clone(),
+                        revision: matches.free[2].clone(),
+                        state: state.clone(),
+                    })
+                }
+                "cache" => match matches.free.get(1).map(|op| op.as_str()) {
+                    Some("clean") => Ok(Operation::CacheClean),
+                    _ => bail!("missing or unknown cache operation [supported: clean]"),
+                },
+                "run" => Ok(Operation::Run {
+                    command: run_command(&matches, command.clone())?,
+                    jobs: jobs(&matches)?,
+                    affected: affected.clone(),
+                }),
                 unknown => {
                     bail!(
-                        "unknown operation '{unknown}' [supported: validate, dependencies, graph]"
+                        "unknown operation '{unknown}' [supported: validate, dependencies, graph, watch, lock, report, files, fingerprint, mark-built, run, cache]"
                     )
                 }
             })
-            .unwrap_or(Ok(Operation::Dependencies))?;
+            .unwrap_or(Ok(dependencies))?;
+
+        if command.is_some() && !matches!(operation, Operation::Run { .. }) {
+            bail!("'-- COMMAND' is only supported by 'run'");
+        }
 
-        let target_dir = matches.opt_str("t").unwrap_or(".".to_owned());
         let target = PathInfo::new(&target_dir, "")?;
         let config_path = matches.opt_str("c");
-        let output = parse_format(
-            matches
-                .opt_str("o")
-                .unwrap_or_else(|| String::from("plain")),
-        )?;
+        let format_template = format_template(&matches)?;
+        let output = match (matches.opt_str("o"), &format_template) {
+            (Some(_), Some(_)) => {
+                bail!("'-o' cannot be combined with '--format-template' or '--template-file'")
+            }
+            (None, Some(_)) => OutputFormat::Template,
+            (output, None) => parse_format(output.unwrap_or_else(|| String::from("plain")))?,
+        };
+
+        let supported_formats = operation.output_formats();
+        if !supported_formats.contains(&output) {
+            let supported: Vec<_> = supported_formats.iter().map(|f| f.to_string()).collect();
+            bail!(
+                "output format '{output}' is not supported by '{}' (supported: {})",
+                operation.name(),
+                supported.join(", ")
+            );
+        }
+
+        let pipeline_template = matches.opt_str("pipeline-template");
+        if output.is_pipeline() && pipeline_template.is_none() {
+            bail!("output format '{output}' requires '--pipeline-template FILE'");
+        }
+
+        let files = matches.opt_present("files");
+        if files
+            && (!matches!(operation, Operation::Dependencies { .. } | Operation::Watch)
+                || !matches!(
+                    output,
+                    OutputFormat::Plain | OutputFormat::Json | OutputFormat::Yaml
+                ))
+        {
+            bail!("'--files' is only supported by 'dependencies' and 'watch' (plain, json, yaml)");
+        }
+
+        let fingerprint = matches.opt_present("fingerprint");
+        if fingerprint
+            && (!matches!(operation, Operation::Dependencies { .. })
+                || !matches!(output, OutputFormat::Json | OutputFormat::Yaml))
+        {
+            bail!("'--fingerprint' is only supported by 'dependencies' (json, yaml)");
+        }
+
+        let shard = shard(&matches)?;
+        if shard.is_some()
+            && !matches!(
+                operation,
+                Operation::Dependencies { .. } | Operation::Run { .. }
+            )
+        {
+            bail!("'--shard' is only supported by 'dependencies' and 'run'");
+        }
+
         let verbose = matches.opt_present("v");
 
         let config = match config_path {
@@ -120,6 +686,7 @@ impl Opts {
 
         let relative = matches.opt_present("relative");
         let all = matches.opt_present("all");
+        let cache = !matches.opt_present("no-cache");
 
         Ok((
             operation,
@@ -130,7 +697,14 @@ impl Opts {
                 verbose,
                 relative,
                 all,
+                files,
+                fingerprint,
+                shard,
+                cache,
                 supported_roots,
+                pipeline_template,
+                format_template,
+                custom_analyzers: Vec::new(),
             },
         ))
     }
@@ -141,12 +715,113 @@ fn parse_format(input: String) -> Result<OutputFormat> {
         "json" => Ok(OutputFormat::Json),
         "plain" => Ok(OutputFormat::Plain),
         "yaml" => Ok(OutputFormat::Yaml),
+        "dot" => Ok(OutputFormat::Dot),
+        "mermaid" => Ok(OutputFormat::Mermaid),
+        "plantuml" => Ok(OutputFormat::PlantUml),
+        "graphml" => Ok(OutputFormat::GraphMl),
+        "github-matrix" => Ok(OutputFormat::GitHubMatrix),
+        "gitlab" => Ok(OutputFormat::GitLab),
+        "buildkite" => Ok(OutputFormat::Buildkite),
+        "markdown" => Ok(OutputFormat::Markdown),
         _ => Err(anyhow!(
-            "invalid output format (supported: plain, json, yaml)"
+            "invalid output format (supported: plain, json, yaml, dot, mermaid, plantuml, graphml, github-matrix, gitlab, buildkite, markdown)"
         )),
     }
 }
 
+/// Parse the user-defined output template given via `--format-template` or `--template-file`.
+fn format_template(matches: &Matches) -> Result<Option<Template>> {
+    match (
+        matches.opt_str("format-template"),
+        matches.opt_str("template-file"),
+    ) {
+        (Some(_), Some(_)) => {
+            bail!("only one of '--format-template' and '--template-file' is allowed")
+        }
+        (Some(template), None) => Ok(Some(Template::parse_inline(&template))),
+        (None, Some(path)) => {
+            let template = std::fs::read_to_string(&path)
+                .map_err(|err| anyhow!("failed to read template file {path}: {err}"))?;
+            Ok(Some(Template::parse(&template)))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+fn graph_options(matches: &Matches) -> Result<GraphOptions> {
+    let focus = matches.opt_str("focus");
+    let depth = matches
+        .opt_str("depth")
+        .map(|depth| {
+            depth
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid depth '{depth}'"))
+        })
+        .transpose()?;
+    let direction = match matches.opt_str("direction").as_deref() {
+        None | Some("both") => Direction::Both,
+        Some("up") => Direction::Up,
+        Some("down") => Direction::Down,
+        Some(other) => bail!("invalid direction '{other}' (supported: up, down, both)"),
+    };
+
+    if focus.is_none() && (depth.is_some() || matches.opt_present("direction")) {
+        bail!("'--depth' and '--direction' require '--focus'");
+    }
+
+    let options = GraphOptions {
+        pattern_nodes: matches.opt_present("pattern-nodes"),
+        global_nodes: matches.opt_present("global-nodes"),
+        focus,
+        depth,
+        direction,
+        affected: matches.opt_present("affected"),
+    };
+
+    if matches.opt_present("diff") && options != GraphOptions::default() {
+        bail!("'--diff' cannot be combined with other graph options");
+    }
+
+    Ok(options)
+}
+
+fn run_command(matches: &Matches, command: Option<Vec<String>>) -> Result<RunCommand> {
+    match (command, matches.free.get(1)) {
+        (Some(command), None) if !command.is_empty() => Ok(RunCommand::Command(command)),
+        (None, Some(task)) if matches.free.len() == 2 => Ok(RunCommand::Task(task.clone())),
+        _ => bail!("'run' expects either a task ('run TASK') or a command ('run -- COMMAND')"),
+    }
+}
+
+fn jobs(matches: &Matches) -> Result<usize> {
+    match matches.opt_str("jobs") {
+        Some(jobs) => match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => Ok(jobs),
+            _ => bail!("invalid number of jobs '{jobs}'"),
+        },
+        None => Ok(std::thread::available_parallelism()
+            .map(|jobs| jobs.get())
+            .unwrap_or(1)),
+    }
+}
+
+fn shard(matches: &Matches) -> Result<Option<Shard>> {
+    let weight = match matches.opt_str("shard-weight").as_deref() {
+        None | Some("depsfile") => ShardWeight::Depsfile,
+        Some("files") => ShardWeight::Files,
+        Some(other) => bail!("invalid shard weight '{other}' (supported: depsfile, files)"),
+    };
+    let topological = matches.opt_present("topological");
+
+    match matches.opt_str("shard") {
+        Some(shard) => Ok(Some(Shard::parse(&shard, weight, topological)?)),
+        None if topological || matches.opt_present("shard-weight") => {
+            bail!("'--shard-weight' and '--topological' require '--shard'")
+        }
+        None => Ok(None),
+    }
+}
+
 fn usage(opts: &Options, exec: &str) {
     let brief = format!(
         r#"Usage: {} [OPERATION] [OPTIONS]
@@ -166,8 +841,38 @@ For instance, you could pipe the git diff output to monodeps:
 
 Operations:
     dependencies    determine dependencies (default)
+                    (use '--base REV' or '--base-lockfile FILE' to also
+                    resolve against a previous dependency graph)
     graph           output service dependencies in dot format
-    validate PATH   validate the given service"#,
+                    (or mermaid/plantuml/json/graphml via '-o')
+                    (use '--pattern-nodes' and '--global-nodes' to include
+                    dependencies outside of any service)
+                    (use '--focus SERVICE' with '--depth N' and
+                    '--direction up|down|both' to render a neighbourhood)
+                    (use '--affected' to highlight the services affected by
+                    the files given on STDIN)
+                    (use '--diff REV' to compare with a git revision)
+    validate PATH   validate the given service
+    watch           print affected services whenever files change
+    lock            write the dependency graph to 'monodeps.lock'
+                    (use '--check' to verify it is up to date)
+    report          write a self-contained HTML report via '--html FILE'
+                    (changed files given on STDIN are highlighted)
+    files SERVICE   list all files the service depends on
+                    (use '--transitive' to include its dependencies and
+                    '--sparse-checkout' to print sparse-checkout patterns)
+    fingerprint [SERVICE]
+                    print the content-hash fingerprint of the service (or
+                    of all services)
+    run TASK        run the Depsfile task in every affected service
+    run -- COMMAND  run the command in every affected service
+                    (in dependency order, use '--jobs N' to limit the
+                    number of parallel commands)
+    mark-built SERVICE REV
+                    record the commit the service was last built from
+                    (use 'dependencies --since-built' to determine the
+                    affected services by the changes since then)
+    cache clean     remove the discovery cache"#,
         exec
     );
 
@@ -178,8 +883,12 @@ Operations:
 mod tests {
     use anyhow::Result;
 
-    use crate::cli::{Operation, OutputFormat};
+    use crate::cli::{
+        AffectedOptions, BaseGraph, Direction, GraphOptions, Operation, OutputFormat,
+    };
     use crate::config::DepsfileType;
+    use crate::run::RunCommand;
+    use crate::shard::{Shard, ShardWeight};
 
     use super::Opts;
 
@@ -196,7 +905,13 @@ mod tests {
     fn empty_args() -> Result<()> {
         let (operation, _opts) = args(vec![])?;
 
-        assert_eq!(Operation::Dependencies, operation);
+        assert_eq!(
+            Operation::Dependencies {
+                affected: AffectedOptions::default(),
+                github_output: false,
+            },
+            operation
+        );
 
         Ok(())
     }
@@ -252,6 +967,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn output_format_graph() -> Result<()> {
+        let (_operation, opts) = args(vec!["graph", "-o", "mermaid"])?;
+        assert_eq!(OutputFormat::Mermaid, opts.output);
+
+        let (_operation, opts) = args(vec!["graph", "-o", "plantuml"])?;
+        assert_eq!(OutputFormat::PlantUml, opts.output);
+
+        let (_operation, opts) = args(vec!["graph", "-o", "graphml"])?;
+        assert_eq!(OutputFormat::GraphMl, opts.output);
+
+        let (_operation, opts) = args(vec!["graph", "-o", "json"])?;
+        assert_eq!(OutputFormat::Json, opts.output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_format_unsupported() -> Result<()> {
+        assert!(args(vec!["-o", "mermaid"]).is_err());
+        assert!(args(vec!["graph", "-o", "yaml"]).is_err());
+        assert!(args(vec!["graph", "--diff", "main", "-o", "json"]).is_err());
+        assert!(args(vec!["watch", "-o", "yaml"]).is_err());
+        assert!(args(vec!["lock", "-o", "json"]).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn output_format_invalid() -> Result<()> {
         let parsed = args(vec!["-o", "unknown"]);
@@ -264,7 +1007,295 @@ mod tests {
     fn operation_dependencies() -> Result<()> {
         let (operation, _opts) = args(vec!["dependencies"])?;
 
-        assert_eq!(Operation::Dependencies, operation);
+        assert_eq!(
+            Operation::Dependencies {
+                affected: AffectedOptions::default(),
+                github_output: false,
+            },
+            operation
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_dependencies_github() -> Result<()> {
+        let (operation, opts) = args(vec!["-o", "github-matrix", "--github-output"])?;
+
+        assert_eq!(OutputFormat::GitHubMatrix, opts.output);
+        assert_eq!(
+            Operation::Dependencies {
+                affected: AffectedOptions::default(),
+                github_output: true,
+            },
+            operation
+        );
+        assert!(args(vec!["graph", "-o", "github-matrix"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_format_pipeline() -> Result<()> {
+        let (_operation, opts) = args(vec!["-o", "gitlab", "--pipeline-template", "ci/job.yml"])?;
+
+        assert_eq!(OutputFormat::GitLab, opts.output);
+        assert_eq!(Some("ci/job.yml".to_string()), opts.pipeline_template);
+        assert!(args(vec!["-o", "gitlab"]).is_err());
+
+        let (_operation, opts) = args(vec!["-o", "buildkite", "--pipeline-template", "step.yml"])?;
+        assert_eq!(OutputFormat::Buildkite, opts.output);
+        assert!(args(vec!["-o", "buildkite"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_format_markdown() -> Result<()> {
+        let (_operation, opts) = args(vec!["dependencies", "-o", "markdown"])?;
+
+        assert_eq!(OutputFormat::Markdown, opts.output);
+        assert!(args(vec!["graph", "-o", "markdown"]).is_err());
+        assert!(args(vec!["watch", "-o", "markdown"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_files() -> Result<()> {
+        let (operation, _opts) = args(vec!["files", "services/api", "--transitive"])?;
+
+        assert_eq!(
+            Operation::Files {
+                service: "services/api".to_string(),
+                transitive: true,
+                sparse_checkout: false
+            },
+            operation
+        );
+        assert!(args(vec!["files"]).is_err());
+        assert!(args(vec!["files", "api", "-o", "yaml"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_dependencies_manifest() -> Result<()> {
+        let (operation, _opts) = args(vec![
+            "--since-manifest",
+            "previous.json",
+            "--write-manifest",
+            "current.json",
+        ])?;
+
+        assert_eq!(
+            Operation::Dependencies {
+                affected: AffectedOptions {
+                    since_manifest: Some("previous.json".to_string()),
+                    write_manifest: Some("current.json".to_string()),
+                    ..Default::default()
+                },
+                github_output: false,
+            },
+            operation
+        );
+        assert!(args(vec!["--since-manifest", "previous.json", "--all"]).is_err());
+        assert!(args(vec!["--since-manifest", "previous.json", "--base", "main"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_mark_built() -> Result<()> {
+        let (operation, _opts) = args(vec!["mark-built", "services/api", "HEAD"])?;
+
+        assert_eq!(
+            Operation::MarkBuilt {
+                service: "services/api".to_string(),
+                revision: "HEAD".to_string(),
+                state: "./monodeps-state.json".to_string(),
+            },
+            operation
+        );
+        assert!(args(vec!["mark-built", "services/api"]).is_err());
+
+        let (operation, _opts) = args(vec!["--since-built", "--state", "state.json"])?;
+        assert!(matches!(
+            operation,
+            Operation::Dependencies { affected: AffectedOptions { since_built: Some(state), .. }, .. }
+                if state == "state.json"
+        ));
+        assert!(args(vec!["--since-built", "--all"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn option_shard() -> Result<()> {
+        let (_operation, opts) = args(vec!["--shard", "2/4", "--topological"])?;
+        assert_eq!(
+            Some(Shard {
+                index: 2,
+                count: 4,
+                weight: ShardWeight::Depsfile,
+                topological: true,
+            }),
+            opts.shard
+        );
+
+        let (_operation, opts) = args(vec!["--shard", "1/2", "--shard-weight", "files"])?;
+        assert_eq!(
+            Some(ShardWeight::Files),
+            opts.shard.map(|shard| shard.weight)
+        );
+
+        assert!(args(vec!["--shard", "3/2"]).is_err());
+        assert!(args(vec!["--shard", "1/2", "--shard-weight", "lines"]).is_err());
+        assert!(args(vec!["--topological"]).is_err());
+        assert!(args(vec!["graph", "--shard", "1/2"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_run() -> Result<()> {
+        let (operation, _opts) = args(vec!["run", "--jobs", "2", "--", "just", "build"])?;
+        assert_eq!(
+            Operation::Run {
+                command: RunCommand::Command(vec!["just".to_string(), "build".to_string()]),
+                jobs: 2,
+                affected: AffectedOptions::default(),
+            },
+            operation
+        );
+
+        let (operation, _opts) = args(vec!["run", "build", "--all"])?;
+        assert!(matches!(
+            operation,
+            Operation::Run { command: RunCommand::Task(task), .. } if task == "build"
+        ));
+
+        let (operation, _opts) = args(vec!["run", "build", "--base", "origin/main"])?;
+        assert!(matches!(
+            operation,
+            Operation::Run { affected: AffectedOptions { base: Some(BaseGraph::Revision(revision)), .. }, .. }
+                if revision == "origin/main"
+        ));
+
+        let (operation, _opts) = args(vec![
+            "run",
+            "build",
+            "--since-manifest",
+            "manifest.json",
+            "--write-manifest",
+            "manifest.json",
+        ])?;
+        assert!(matches!(
+            operation,
+            Operation::Run {
+                affected: AffectedOptions { since_manifest: Some(since), write_manifest: Some(write), .. },
+                ..
+            } if since == "manifest.json" && write == "manifest.json"
+        ));
+
+        let (operation, _opts) = args(vec!["run", "build", "--since-built"])?;
+        assert!(matches!(
+            operation,
+            Operation::Run {
+                affected: AffectedOptions {
+                    since_built: Some(_),
+                    ..
+                },
+                ..
+            }
+        ));
+
+        assert!(args(vec!["run"]).is_err());
+        assert!(args(vec!["run", "--"]).is_err());
+        assert!(args(vec!["run", "build", "--", "just", "build"]).is_err());
+        assert!(args(vec!["run", "build", "--jobs", "0"]).is_err());
+        assert!(args(vec!["graph", "--", "just", "build"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_fingerprint() -> Result<()> {
+        let (operation, _opts) = args(vec!["fingerprint", "api"])?;
+        assert_eq!(Operation::Fingerprint(Some("api".to_string())), operation);
+
+        let (operation, _opts) = args(vec!["fingerprint", "-o", "json"])?;
+        assert_eq!(Operation::Fingerprint(None), operation);
+
+        let (_operation, opts) = args(vec!["-o", "json", "--fingerprint"])?;
+        assert!(opts.fingerprint);
+        assert!(args(vec!["--fingerprint"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn option_files() -> Result<()> {
+        let (_operation, opts) = args(vec!["--files", "-o", "json"])?;
+        assert!(opts.files);
+
+        let (_operation, opts) = args(vec!["dependencies"])?;
+        assert!(!opts.files);
+
+        assert!(args(vec!["--files", "-o", "github-matrix"]).is_err());
+        assert!(args(vec!["graph", "--files"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_format_template() -> Result<()> {
+        let (_operation, opts) = args(vec!["--format-template", "{{path}}\\n"])?;
+
+        assert_eq!(OutputFormat::Template, opts.output);
+        assert_eq!(
+            Some("{{path}}\n"),
+            opts.format_template.as_ref().map(|t| t.item.as_str())
+        );
+
+        let (_operation, opts) = args(vec!["watch", "--format-template", "{{name}}"])?;
+        assert_eq!(OutputFormat::Template, opts.output);
+
+        assert!(args(vec!["-o", "json", "--format-template", "{{path}}"]).is_err());
+        assert!(args(vec!["graph", "--format-template", "{{path}}"]).is_err());
+        assert!(args(vec!["--format-template", "x", "--template-file", "y"]).is_err());
+        assert!(args(vec!["--template-file", "does/not/exist"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_dependencies_base() -> Result<()> {
+        let (operation, _opts) = args(vec!["--base", "origin/main"])?;
+        assert_eq!(
+            Operation::Dependencies {
+                affected: AffectedOptions {
+                    base: Some(BaseGraph::Revision("origin/main".to_string())),
+                    ..Default::default()
+                },
+                github_output: false,
+            },
+            operation
+        );
+
+        let (operation, _opts) = args(vec!["dependencies", "--base-lockfile", "old.lock"])?;
+        assert_eq!(
+            Operation::Dependencies {
+                affected: AffectedOptions {
+                    base: Some(BaseGraph::Lockfile("old.lock".to_string())),
+                    ..Default::default()
+                },
+                github_output: false,
+            },
+            operation
+        );
+
+        assert!(args(vec!["--base", "main", "--base-lockfile", "old.lock"]).is_err());
 
         Ok(())
     }
@@ -273,7 +1304,102 @@ mod tests {
     fn operation_graph() -> Result<()> {
         let (operation, _opts) = args(vec!["graph"])?;
 
-        assert_eq!(Operation::Graph, operation);
+        assert_eq!(
+            Operation::Graph {
+                diff: None,
+                options: GraphOptions::default()
+            },
+            operation
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_graph_nodes() -> Result<()> {
+        let (operation, _opts) = args(vec!["graph", "--pattern-nodes", "--global-nodes"])?;
+
+        assert_eq!(
+            Operation::Graph {
+                diff: None,
+                options: GraphOptions {
+                    pattern_nodes: true,
+                    global_nodes: true,
+                    ..Default::default()
+                }
+            },
+            operation
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_graph_focus() -> Result<()> {
+        let (operation, _opts) = args(vec![
+            "graph",
+            "--focus",
+            "services/auth",
+            "--depth",
+            "2",
+            "--direction",
+            "up",
+            "--affected",
+        ])?;
+
+        assert_eq!(
+            Operation::Graph {
+                diff: None,
+                options: GraphOptions {
+                    focus: Some("services/auth".to_string()),
+                    depth: Some(2),
+                    direction: Direction::Up,
+                    affected: true,
+                    ..Default::default()
+                }
+            },
+            operation
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_graph_focus_error() -> Result<()> {
+        assert!(args(vec!["graph", "--depth", "2"]).is_err());
+        assert!(args(vec!["graph", "--focus", "auth", "--depth", "x"]).is_err());
+        assert!(args(vec!["graph", "--focus", "auth", "--direction", "left"]).is_err());
+        assert!(args(vec!["graph", "--diff", "main", "--focus", "auth"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_graph_diff() -> Result<()> {
+        let (operation, _opts) = args(vec!["graph", "--diff", "origin/main"])?;
+
+        assert_eq!(
+            Operation::Graph {
+                diff: Some("origin/main".to_string()),
+                options: GraphOptions::default()
+            },
+            operation
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_report() -> Result<()> {
+        let (operation, _opts) = args(vec!["report", "--html", "out.html"])?;
+
+        assert_eq!(
+            Operation::Report {
+                html: "out.html".to_string()
+            },
+            operation
+        );
+        assert!(args(vec!["report"]).is_err());
 
         Ok(())
     }
@@ -296,6 +1422,55 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn operation_watch() -> Result<()> {
+        let (operation, _opts) = args(vec!["watch"])?;
+
+        assert_eq!(Operation::Watch, operation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_lock() -> Result<()> {
+        let (operation, _opts) = args(vec!["lock"])?;
+        assert_eq!(Operation::Lock { check: false }, operation);
+
+        let (operation, _opts) = args(vec!["lock", "--check"])?;
+        assert_eq!(Operation::Lock { check: true }, operation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_cache_clean() -> Result<()> {
+        let (operation, _opts) = args(vec!["cache", "clean"])?;
+
+        assert_eq!(Operation::CacheClean, operation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn operation_cache_unknown() -> Result<()> {
+        let result = args(vec!["cache", "whatever"]);
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_cache() -> Result<()> {
+        let (_operation, opts) = args(vec![])?;
+        assert!(opts.cache);
+
+        let (_operation, opts) = args(vec!["--no-cache"])?;
+        assert!(!opts.cache);
+
+        Ok(())
+    }
+
     #[test]
     fn operation_unknown() -> Result<()> {
         let result = args(vec!["whatever"]);
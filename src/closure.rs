@@ -0,0 +1,317 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+
+use crate::cli::{Direction, Opts, OutputFormat};
+use crate::config::DepPattern;
+use crate::git;
+use crate::graph::Graph;
+use crate::service::{Service, input_files};
+
+/// Determine the input closure of the given `service` (name or path relative to the target
+/// directory): all files inside of the service directory, matching any of its explicit or
+/// auto-discovered dependencies or any global dependency. If `transitive` is set, the same
+/// applies to all services the service (transitively) depends on.
+///
+/// Returns the sorted list of canonicalized file paths.
+pub fn files(service: &str, transitive: bool, opts: &Opts) -> Result<Vec<String>> {
+    let graph = Graph::new(Service::discover(opts)?);
+    let id = graph
+        .find(service, opts)
+        .map(|node| node.id.clone())
+        .ok_or_else(|| anyhow!("unknown service '{service}'"))?;
+
+    let graph = if transitive {
+        graph.focus(&id, None, Direction::Down)
+    } else {
+        graph.focus(&id, Some(0), Direction::Down)
+    };
+
+    let services: Vec<_> = graph.nodes.iter().map(|node| &node.service).collect();
+    let patterns = patterns(&services, opts);
+    let candidates = candidates(&patterns, opts);
+
+    Ok(matching(&candidates, &services, &patterns))
+}
+
+/// All explicit and auto-discovered dependencies of the given `services` as well as the global
+/// dependencies.
+pub(crate) fn patterns<'a>(services: &[&'a Service], opts: &'a Opts) -> Vec<&'a DepPattern> {
+    services
+        .iter()
+        .flat_map(|svc| {
+            svc.depsfile
+                .dependencies
+                .iter()
+                .chain(svc.auto_dependencies.iter().map(|auto| &auto.pattern))
+        })
+        .chain(opts.config.global_dependencies.iter())
+        .collect()
+}
+
+/// All files of the target directory and of the dependencies in `patterns` that point outside of
+/// it, sorted. Hidden files are included, only `.git` and `node_modules` are skipped (see
+/// `input_files`).
+pub(crate) fn candidates(patterns: &[&DepPattern], opts: &Opts) -> Vec<String> {
+    // explicit dependencies may point outside of the target directory as well
+    let mut roots = BTreeSet::from([opts.target.canonicalized.clone()]);
+    for path in patterns.iter().filter_map(|pattern| pattern.hash()) {
+        if !is_within(path, &opts.target.canonicalized) {
+            roots.insert(path.to_owned());
+        }
+    }
+
+    let mut files = BTreeSet::new();
+
+    for root in roots {
+        for entry in input_files(&root) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            if let Some(path) = entry.path().to_str() {
+                files.insert(path.to_owned());
+            }
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+/// All files of `candidates` inside of any of the `services` or matching any of the `patterns`.
+pub(crate) fn matching(
+    candidates: &[String],
+    services: &[&Service],
+    patterns: &[&DepPattern],
+) -> Vec<String> {
+    candidates
+        .iter()
+        .filter(|path| {
+            services
+                .iter()
+                .any(|svc| is_within(path, &svc.path.canonicalized))
+                || patterns.iter().any(|pattern| pattern.is_match(path))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Git sparse-checkout patterns (cone mode) that include all of the given (canonicalized)
+/// `files`, relative to the repository `root`. Files outside of `root` are skipped.
+pub fn sparse_checkout_patterns(files: &[String], root: &str) -> Vec<String> {
+    // directories that are included recursively
+    let mut recursive = BTreeSet::new();
+    for file in files {
+        let Ok(relative) = Path::new(file).strip_prefix(root) else {
+            continue;
+        };
+        if let Some(dir) = relative.parent().and_then(|dir| dir.to_str())
+            && !dir.is_empty()
+        {
+            recursive.insert(dir.to_owned());
+        }
+    }
+
+    // nested directories are already included by their parents
+    let recursive: Vec<_> = recursive
+        .iter()
+        .filter(|dir| {
+            !recursive
+                .iter()
+                .any(|other| other != *dir && is_within(dir, other))
+        })
+        .cloned()
+        .collect();
+
+    // all parents of the recursive directories are included without their sub directories
+    let parents: BTreeSet<_> = recursive
+        .iter()
+        .flat_map(|dir| {
+            Path::new(dir)
+                .ancestors()
+                .skip(1)
+                .filter_map(|parent| parent.to_str())
+                .filter(|parent| !parent.is_empty())
+                .map(|parent| parent.to_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut entries: Vec<_> = parents
+        .iter()
+        .map(|dir| (dir.as_str(), false))
+        .chain(recursive.iter().map(|dir| (dir.as_str(), true)))
+        .collect();
+    entries.sort();
+
+    let mut patterns = vec!["/*".to_owned(), "!/*/".to_owned()];
+    for (dir, recursive) in entries {
+        patterns.push(format!("/{dir}/"));
+        if !recursive {
+            patterns.push(format!("!/{dir}/*/"));
+        }
+    }
+
+    patterns
+}
+
+/// Write the input closure of the given `service` (see `files`) or the respective sparse-checkout
+/// patterns (see `sparse_checkout_patterns`) in the output format of the given `Opts`.
+pub fn output<W>(
+    service: &str,
+    transitive: bool,
+    sparse_checkout: bool,
+    opts: &Opts,
+    w: &mut W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let files = files(service, transitive, opts)?;
+
+    let lines = if sparse_checkout {
+        // the patterns are relative to the repository and not to the target directory
+        let root = git::toplevel(&opts.target.canonicalized)
+            .ok()
+            .and_then(|root| root.canonicalize().ok())
+            .and_then(|root| root.to_str().map(|root| root.to_owned()))
+            .unwrap_or_else(|| opts.target.canonicalized.clone());
+
+        sparse_checkout_patterns(&files, &root)
+    } else if opts.relative {
+        files
+            .iter()
+            .map(
+                |file| match Path::new(file).strip_prefix(&opts.target.canonicalized) {
+                    Ok(relative) => format!("./{}", relative.display()),
+                    Err(_) => file.clone(),
+                },
+            )
+            .collect()
+    } else {
+        files
+    };
+
+    match opts.output {
+        OutputFormat::Json => serde_json::to_writer(&mut *w, &lines)?,
+        _ => {
+            for line in lines {
+                writeln!(w, "{line}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the given `path` is the directory `dir` itself or inside of it.
+fn is_within(path: &str, dir: &str) -> bool {
+    Path::new(path).starts_with(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::test_util::create_file;
+
+    use super::{files, sparse_checkout_patterns};
+
+    fn closure(service: &str, transitive: bool) -> Result<Vec<String>> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "libs/core/Depsfile", "")?;
+        create_file(dir.path(), "libs/core/core.go", "")?;
+        create_file(
+            dir.path(),
+            "libs/auth/Depsfile",
+            "dependencies:\n  - ../core\n",
+        )?;
+        create_file(dir.path(), "libs/auth/auth.go", "")?;
+        create_file(
+            dir.path(),
+            "services/api/Depsfile",
+            "dependencies:\n  - ../../libs/auth\n  - proto/*.proto\n  - ../../config\n",
+        )?;
+        create_file(dir.path(), "services/api/main.go", "")?;
+        create_file(dir.path(), "services/api/.env", "")?;
+        create_file(dir.path(), "services/api/node_modules/lib/index.js", "")?;
+        create_file(dir.path(), ".git/HEAD", "")?;
+        create_file(dir.path(), "proto/api.proto", "")?;
+        create_file(dir.path(), "proto/README.md", "")?;
+        create_file(dir.path(), "config/api.yaml", "")?;
+        create_file(dir.path(), "README.md", "")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let root = opts.target.canonicalized.clone();
+
+        Ok(files(service, transitive, &opts)?
+            .into_iter()
+            .map(|file| file.trim_start_matches(&root).to_owned())
+            .collect())
+    }
+
+    #[test]
+    fn service_closure() -> Result<()> {
+        assert_eq!(
+            vec![
+                "/config/api.yaml",
+                "/libs/auth/Depsfile",
+                "/libs/auth/auth.go",
+                "/proto/api.proto",
+                "/services/api/.env",
+                "/services/api/Depsfile",
+                "/services/api/main.go",
+            ],
+            closure("services/api", false)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn service_closure_transitive() -> Result<()> {
+        let files = closure("./services/api/", true)?;
+
+        assert!(files.contains(&"/libs/core/core.go".to_string()));
+        assert!(files.contains(&"/libs/core/Depsfile".to_string()));
+        assert!(!files.contains(&"/README.md".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn service_closure_unknown() {
+        assert!(closure("unknown", false).is_err());
+    }
+
+    #[test]
+    fn cone_patterns() {
+        let files = vec![
+            "/repo/README.md".to_string(),
+            "/repo/libs/auth/auth.go".to_string(),
+            "/repo/libs/auth/internal/token.go".to_string(),
+            "/repo/services/api/main.go".to_string(),
+            "/repo/proto/api.proto".to_string(),
+            "/other/file".to_string(),
+        ];
+
+        assert_eq!(
+            vec![
+                "/*",
+                "!/*/",
+                "/libs/",
+                "!/libs/*/",
+                "/libs/auth/",
+                "/proto/",
+                "/services/",
+                "!/services/*/",
+                "/services/api/",
+            ],
+            sparse_checkout_patterns(&files, "/repo")
+        );
+    }
+}
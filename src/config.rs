@@ -1,35 +1,90 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{Result, anyhow};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use yaml_rust::Yaml;
 
 use crate::path::PathInfo;
 use crate::utils::{load_yaml, yaml_str_list};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Config {
     pub auto_discovery: AutoDiscoveryConfig,
     pub global_dependencies: Vec<DepPattern>,
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone)]
 pub struct AutoDiscoveryConfig {
     pub go: GoDepsConfig,
     pub dotnet: DotnetConfig,
+    pub plugins: Vec<PluginConfig>,
+    pub references: Vec<ReferenceConfig>,
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone)]
 pub struct GoDepsConfig {
     pub package_prefixes: Vec<String>,
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone)]
 pub struct DotnetConfig {
     pub package_namespaces: Vec<String>,
 }
 
+/// Default timeout of external analyzer plugins.
+const PLUGIN_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configuration of an external analyzer plugin. The plugin `command` is invoked for every
+/// service that contains any file matching the `files` globs.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PluginConfig {
+    pub name: String,
+    pub files: Vec<String>,
+    pub command: Vec<String>,
+    pub timeout: Duration,
+}
+
+/// Base directory that references found by a `ReferenceConfig` analyzer are relative to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReferenceBase {
+    /// relative to the file the reference was found in
+    File,
+    /// relative to the service directory
+    Service,
+    /// relative to the repository root
+    Root,
+}
+
+impl TryFrom<&str> for ReferenceBase {
+    type Error = String;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value {
+            "file" => Ok(ReferenceBase::File),
+            "service" => Ok(ReferenceBase::Service),
+            "root" => Ok(ReferenceBase::Root),
+            unknown => Err(format!(
+                "unknown reference base '{unknown}' [supported: file, service, root]"
+            )),
+        }
+    }
+}
+
+/// Configuration of a declarative, regex based analyzer. Every line of the files matching the
+/// `files` globs is matched against `regex` whose first capture group is the referenced path.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReferenceConfig {
+    pub name: String,
+    pub files: Vec<String>,
+    pub regex: String,
+    pub relative_to: ReferenceBase,
+    pub recursive: bool,
+}
+
 impl Config {
     pub fn new(path: &str, root: &PathInfo) -> Result<Config> {
         let yaml = load_yaml(path)?;
@@ -51,6 +106,20 @@ impl Config {
         let dotnet_disc = &auto_disc["dotnet"];
         let dotnet_package_namespaces = yaml_str_list(&dotnet_disc["package_namespaces"]);
 
+        let plugins = auto_disc["plugins"]
+            .as_vec()
+            .unwrap_or(&empty)
+            .iter()
+            .flat_map(|elem| parse_plugin(elem, path))
+            .collect();
+
+        let references = auto_disc["references"]
+            .as_vec()
+            .unwrap_or(&empty)
+            .iter()
+            .flat_map(|elem| parse_reference(elem, path))
+            .collect();
+
         Ok(Config {
             auto_discovery: AutoDiscoveryConfig {
                 go: GoDepsConfig {
@@ -59,6 +128,8 @@ impl Config {
                 dotnet: DotnetConfig {
                     package_namespaces: dotnet_package_namespaces,
                 },
+                plugins,
+                references,
             },
             global_dependencies,
         })
@@ -74,16 +145,47 @@ impl Config {
             Language::Protobuf => true,
             Language::Justfile => true,
             Language::Makefile => true,
+            Language::Custom(_) => true,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "SerializedDepPattern", try_from = "SerializedDepPattern")]
 pub struct DepPattern {
     raw: PathInfo,
     pattern: Option<Regex>,
 }
 
+/// Serializable representation of a `DepPattern` (e.g. for the discovery cache).
+#[derive(Serialize, Deserialize)]
+struct SerializedDepPattern {
+    path: PathInfo,
+    regex: Option<String>,
+}
+
+impl From<DepPattern> for SerializedDepPattern {
+    fn from(value: DepPattern) -> Self {
+        Self {
+            path: value.raw,
+            regex: value.pattern.map(|rgx| rgx.as_str().to_owned()),
+        }
+    }
+}
+
+impl TryFrom<SerializedDepPattern> for DepPattern {
+    type Error = regex::Error;
+
+    fn try_from(value: SerializedDepPattern) -> std::result::Result<Self, Self::Error> {
+        let pattern = value.regex.as_deref().map(Regex::new).transpose()?;
+
+        Ok(Self {
+            raw: value.path,
+            pattern,
+        })
+    }
+}
+
 impl DepPattern {
     /// Creates a new `DepPattern` given the `dependency` path (often a relative path) and the
     /// `root_dir`, the `dependency` is relative to. The `dependency` however can also be a
@@ -180,20 +282,31 @@ impl Display for DepPattern {
 }
 
 fn to_glob_regex(pattern: &str) -> Result<Regex> {
-    let prepared = pattern
+    let rgx = Regex::new(&glob_regex_str(pattern))?;
+    Ok(rgx)
+}
+
+/// Convert the given glob `pattern` into a regular expression that has to match a file name as a
+/// whole (e.g. `*.bzl` or `BUILD`).
+pub(crate) fn to_file_name_glob(pattern: &str) -> Result<Regex> {
+    let rgx = Regex::new(&format!("^{}$", glob_regex_str(pattern)))?;
+    Ok(rgx)
+}
+
+fn glob_regex_str(pattern: &str) -> String {
+    pattern
         .replace(".", "\\.")
         .replace("**", ".+")
         .replace("*", "[^/\\\\]+")
-        .replace("?", ".");
-
-    let rgx = Regex::new(&prepared)?;
-    Ok(rgx)
+        .replace("?", ".")
 }
 
 /// List of supported languages/frameworks
 ///
 /// NOTE: remember to extend `VALUES` below
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+#[non_exhaustive]
 pub enum Language {
     Golang,
     Dotnet,
@@ -203,6 +316,8 @@ pub enum Language {
     Protobuf,
     Justfile,
     Makefile,
+    /// Language of a custom `LanguageAnalyzer` that is identified by its name.
+    Custom(String),
 }
 
 impl Language {
@@ -229,10 +344,23 @@ impl Display for Language {
             Language::Protobuf => f.write_str("proto"),
             Language::Justfile => f.write_str("justfile"),
             Language::Makefile => f.write_str("makefile"),
+            Language::Custom(name) => f.write_str(name),
         }
     }
 }
 
+impl From<Language> for String {
+    fn from(value: Language) -> Self {
+        value.to_string()
+    }
+}
+
+impl From<String> for Language {
+    fn from(value: String) -> Self {
+        value.as_str().try_into().unwrap_or(Language::Custom(value))
+    }
+}
+
 impl TryFrom<&str> for Language {
     type Error = String;
 
@@ -252,7 +380,8 @@ impl TryFrom<&str> for Language {
     }
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum DepsfileType {
     Depsfile,
     Buildfile,
@@ -260,10 +389,16 @@ pub enum DepsfileType {
     Makefile,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Depsfile {
     pub dependencies: Vec<DepPattern>,
     pub languages: Vec<Language>,
+    /// Relative build cost of the service that is used to balance shards (see `--shard`).
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// Shell commands by task name that can be executed via `monodeps run TASK`.
+    #[serde(default)]
+    pub tasks: BTreeMap<String, String>,
 }
 
 impl Depsfile {
@@ -292,6 +427,8 @@ impl Depsfile {
         Depsfile {
             dependencies: Vec::new(),
             languages: Vec::new(),
+            weight: None,
+            tasks: BTreeMap::new(),
         }
     }
 
@@ -317,7 +454,23 @@ impl Depsfile {
             .flat_map(|elem| parse_dependency(elem, &file, dir, &root_dir))
             .collect();
 
-        let known_keys = ["languages", "dependencies"];
+        let weight = match &config_yaml["weight"] {
+            Yaml::BadValue => None,
+            value => {
+                let weight = value.as_i64().and_then(|weight| u32::try_from(weight).ok());
+                if weight.is_none() {
+                    log::warn!(
+                        "{}: invalid weight (expecting a non-negative integer)",
+                        file.as_ref().display()
+                    );
+                }
+                weight
+            }
+        };
+
+        let tasks = parse_tasks(&config_yaml["tasks"], &file);
+
+        let known_keys = ["languages", "dependencies", "weight", "tasks"];
 
         // warn about unknown configuration values
         if log::log_enabled!(log::Level::Warn) {
@@ -339,6 +492,8 @@ impl Depsfile {
         Ok(Depsfile {
             dependencies,
             languages,
+            weight,
+            tasks,
         })
     }
 
@@ -377,10 +532,42 @@ impl Depsfile {
         Ok(Depsfile {
             dependencies,
             languages,
+            weight: None,
+            tasks: BTreeMap::new(),
         })
     }
 }
 
+/// Parse the `tasks` map of a Depsfile, skipping (and warning about) all invalid entries.
+fn parse_tasks<P>(yaml: &Yaml, path: P) -> BTreeMap<String, String>
+where
+    P: AsRef<Path>,
+{
+    let Some(hash) = yaml.as_hash() else {
+        if !yaml.is_badvalue() {
+            log::warn!(
+                "{}: invalid tasks (expecting a map)",
+                path.as_ref().display()
+            );
+        }
+        return BTreeMap::new();
+    };
+
+    hash.iter()
+        .filter_map(|(name, command)| match (name.as_str(), command.as_str()) {
+            (Some(name), Some(command)) => Some((name.to_owned(), command.to_owned())),
+            _ => {
+                log::warn!(
+                    "{}: invalid task {:?} (expecting a command string)",
+                    path.as_ref().display(),
+                    name
+                );
+                None
+            }
+        })
+        .collect()
+}
+
 fn parse_dependency<P, D, R>(
     yaml: &Yaml,
     path: P,
@@ -411,6 +598,115 @@ where
     pattern
 }
 
+fn parse_plugin<P>(yaml: &Yaml, path: P) -> Result<PluginConfig>
+where
+    P: AsRef<Path>,
+{
+    let plugin = parse_plugin_config(yaml);
+
+    if let Err(err) = &plugin {
+        log::warn!("{}: invalid plugin: {}", path.as_ref().display(), err);
+    }
+
+    plugin
+}
+
+fn parse_plugin_config(yaml: &Yaml) -> Result<PluginConfig> {
+    let name = yaml["name"]
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("missing 'name' property"))?
+        .to_owned();
+
+    let files = yaml_str_list(&yaml["files"]);
+    if files.is_empty() {
+        return Err(anyhow!("{name}: missing 'files' globs"));
+    }
+
+    // the command may be given as a single string or a list of arguments
+    let command = match yaml["command"].as_str() {
+        Some(cmd) => cmd.split_whitespace().map(|arg| arg.to_owned()).collect(),
+        None => yaml_str_list(&yaml["command"]),
+    };
+    if command.is_empty() {
+        return Err(anyhow!("{name}: missing 'command'"));
+    }
+
+    let timeout = match &yaml["timeout"] {
+        Yaml::Integer(secs) if *secs > 0 => Duration::from_secs(*secs as u64),
+        Yaml::Real(_) => yaml["timeout"]
+            .as_f64()
+            .filter(|secs| *secs > 0.0)
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| anyhow!("{name}: invalid 'timeout'"))?,
+        Yaml::BadValue => PLUGIN_DEFAULT_TIMEOUT,
+        _ => return Err(anyhow!("{name}: invalid 'timeout'")),
+    };
+
+    Ok(PluginConfig {
+        name,
+        files,
+        command,
+        timeout,
+    })
+}
+
+fn parse_reference<P>(yaml: &Yaml, path: P) -> Result<ReferenceConfig>
+where
+    P: AsRef<Path>,
+{
+    let reference = parse_reference_config(yaml);
+
+    if let Err(err) = &reference {
+        log::warn!(
+            "{}: invalid reference analyzer: {}",
+            path.as_ref().display(),
+            err
+        );
+    }
+
+    reference
+}
+
+fn parse_reference_config(yaml: &Yaml) -> Result<ReferenceConfig> {
+    let name = yaml["name"]
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("missing 'name' property"))?
+        .to_owned();
+
+    let files = yaml_str_list(&yaml["files"]);
+    if files.is_empty() {
+        return Err(anyhow!("{name}: missing 'files' globs"));
+    }
+
+    let regex = yaml["regex"]
+        .as_str()
+        .ok_or_else(|| anyhow!("{name}: missing 'regex'"))?
+        .to_owned();
+
+    if Regex::new(&regex)?.captures_len() < 2 {
+        return Err(anyhow!("{name}: 'regex' requires a capture group"));
+    }
+
+    let relative_to = yaml["relative_to"]
+        .as_str()
+        .map(ReferenceBase::try_from)
+        .transpose()
+        .map_err(|err| anyhow!("{name}: {err}"))?
+        .unwrap_or(ReferenceBase::File);
+
+    let recursive = yaml["recursive"].as_bool().unwrap_or(false);
+
+    Ok(ReferenceConfig {
+        name,
+        files,
+        regex,
+        relative_to,
+        recursive,
+    })
+}
+
 fn parse_languages<P>(value: &Yaml, file: P) -> Vec<Language>
 where
     P: AsRef<Path>,
@@ -434,6 +730,7 @@ mod tests {
     use std::fs::File;
     use std::io::Write;
     use std::path::Path;
+    use std::time::Duration;
 
     use anyhow::Result;
     use tempfile::TempDir;
@@ -441,6 +738,7 @@ mod tests {
 
     use crate::config::{
         AutoDiscoveryConfig, Depsfile, DepsfileType, DotnetConfig, GoDepsConfig, Language,
+        PluginConfig, ReferenceBase, ReferenceConfig,
     };
     use crate::path::PathInfo;
 
@@ -496,7 +794,9 @@ global_dependencies:
                 },
                 dotnet: DotnetConfig {
                     package_namespaces: vec!["Foo.Bar".to_string()]
-                }
+                },
+                plugins: vec![],
+                references: vec![],
             },
             result.auto_discovery
         );
@@ -504,6 +804,120 @@ global_dependencies:
         Ok(())
     }
 
+    #[test]
+    fn load_config_plugins() -> Result<()> {
+        let dir = tmp()?;
+        let config_name = "config.yaml";
+
+        create_file(
+            dir.path(),
+            config_name,
+            r#"
+auto_discovery:
+  plugins:
+    - name: bazel
+      files:
+        - BUILD
+        - '*.bzl'
+      command: ./tools/bazel-deps --json
+      timeout: 5
+    - name: sql
+      files:
+        - '*.sql'
+      command:
+        - sql-deps
+        - --include-dirs
+    # this one is invalid (missing command):
+    - name: invalid
+      files:
+        - '*.txt'
+"#,
+        )?;
+
+        let root = PathInfo::new(dir.path(), "")?;
+        let result = Config::new(dir.path().join(config_name).to_str().unwrap(), &root)?;
+
+        assert_eq!(
+            vec![
+                PluginConfig {
+                    name: "bazel".to_string(),
+                    files: vec!["BUILD".to_string(), "*.bzl".to_string()],
+                    command: vec!["./tools/bazel-deps".to_string(), "--json".to_string()],
+                    timeout: Duration::from_secs(5),
+                },
+                PluginConfig {
+                    name: "sql".to_string(),
+                    files: vec!["*.sql".to_string()],
+                    command: vec!["sql-deps".to_string(), "--include-dirs".to_string()],
+                    timeout: Duration::from_secs(30),
+                },
+            ],
+            result.auto_discovery.plugins
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_config_references() -> Result<()> {
+        let dir = tmp()?;
+        let config_name = "config.yaml";
+
+        create_file(
+            dir.path(),
+            config_name,
+            r#"
+auto_discovery:
+  references:
+    - name: deploy-conf
+      files:
+        - deploy.conf
+      regex: '^include "([^"]+)"'
+      recursive: true
+    - name: sql
+      files:
+        - '*.sql'
+      regex: '^\\i (\S+)'
+      relative_to: root
+    # these are invalid:
+    - name: no-capture-group
+      files:
+        - '*.env'
+      regex: '^source .+'
+    - name: unknown-base
+      files:
+        - '*.env'
+      regex: '^source (.+)'
+      relative_to: somewhere
+"#,
+        )?;
+
+        let root = PathInfo::new(dir.path(), "")?;
+        let result = Config::new(dir.path().join(config_name).to_str().unwrap(), &root)?;
+
+        assert_eq!(
+            vec![
+                ReferenceConfig {
+                    name: "deploy-conf".to_string(),
+                    files: vec!["deploy.conf".to_string()],
+                    regex: "^include \"([^\"]+)\"".to_string(),
+                    relative_to: ReferenceBase::File,
+                    recursive: true,
+                },
+                ReferenceConfig {
+                    name: "sql".to_string(),
+                    files: vec!["*.sql".to_string()],
+                    regex: "^\\\\i (\\S+)".to_string(),
+                    relative_to: ReferenceBase::Root,
+                    recursive: false,
+                },
+            ],
+            result.auto_discovery.references
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn load_depsfile_empty() {
         let depsfile = Depsfile::depsfile_from_yaml(Yaml::from_str(""), "/tmp/some/where", "/tmp");
@@ -565,6 +979,45 @@ dependencies:
         Ok(())
     }
 
+    #[test]
+    fn load_depsfile_weight() -> Result<()> {
+        let dir = tmp()?;
+        create_file(dir.path(), "Depsfile", "weight: 5\n")?;
+        create_file(dir.path(), "Invalid", "weight: -1\n")?;
+
+        let depsfile = Depsfile::load(DepsfileType::Depsfile, dir.path().join("Depsfile"), "/tmp")?;
+        assert_eq!(Some(5), depsfile.weight);
+
+        let depsfile = Depsfile::load(DepsfileType::Depsfile, dir.path().join("Invalid"), "/tmp")?;
+        assert_eq!(None, depsfile.weight);
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_depsfile_tasks() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "Depsfile",
+            "tasks:\n  build: just build\n  test: go test ./...\n  invalid: [1, 2]\n",
+        )?;
+
+        let depsfile = Depsfile::load(DepsfileType::Depsfile, dir.path().join("Depsfile"), "/tmp")?;
+
+        assert_eq!(2, depsfile.tasks.len());
+        assert_eq!(
+            Some("just build"),
+            depsfile.tasks.get("build").map(|t| t.as_str())
+        );
+        assert_eq!(
+            Some("go test ./..."),
+            depsfile.tasks.get("test").map(|t| t.as_str())
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn load_buildfile() -> Result<()> {
         let dir = tmp()?;
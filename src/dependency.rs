@@ -1,20 +1,51 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::cli::Opts;
+use crate::config::{DepPattern, Language};
+use crate::lock::{Lockfile, relative_name};
 use crate::path::PathInfo;
 use crate::service::{BuildTrigger, Service};
 use anyhow::{Result, anyhow};
 
 pub fn resolve(
+    services: Vec<Service>,
+    changed_files: Vec<String>,
+    opts: &Opts,
+) -> Result<Vec<Service>> {
+    resolve_graphs(services, changed_files, None, opts)
+}
+
+/// Resolve the changed files against the union of the current dependency graph (the given
+/// `services`) and the `base` dependency graph, e.g. of the target branch. That way services are
+/// determined as well, whose dependency on an affected service or on a changed file was removed in
+/// the meantime. These services are triggered by a `BuildTrigger::BaseDependency`.
+pub fn resolve_with_base(
+    services: Vec<Service>,
+    changed_files: Vec<String>,
+    base: &Lockfile,
+    opts: &Opts,
+) -> Result<Vec<Service>> {
+    resolve_graphs(services, changed_files, Some(base), opts)
+}
+
+fn resolve_graphs(
     mut services: Vec<Service>,
     changed_files: Vec<String>,
+    base: Option<&Lockfile>,
     opts: &Opts,
 ) -> Result<Vec<Service>> {
-    let canon_changed_files: Vec<_> = changed_files
+    let mut canon_changed_files: Vec<_> = changed_files
         .into_iter()
         .flat_map(|p| PathInfo::new(&p, &opts.target.canonicalized))
         .collect();
 
+    // the order of the changed files determines the reported trigger sources
+    canon_changed_files.sort_by(|a, b| a.canonicalized.cmp(&b.canonicalized));
+    canon_changed_files.dedup_by(|a, b| a.canonicalized == b.canonicalized);
+
+    // 0. record the changed files of every service
+    record_changed_files(&mut services, &canon_changed_files, opts)?;
+
     // 1. check global dependencies
     // if any changed file matches any global dependency every service will be returned
     for global_dep in opts.config.global_dependencies.iter() {
@@ -29,6 +60,10 @@ pub fn resolve(
         }
     }
 
+    let base_patterns = base
+        .map(|base| base_patterns(base, &services, opts))
+        .transpose()?;
+
     let mut service_map: HashMap<String, Service> = services
         .into_iter()
         .map(|svc| (svc.path.canonicalized.clone(), svc))
@@ -53,11 +88,30 @@ pub fn resolve(
         &mut service_map,
         &canon_changed_files,
     )?);
+    if let Some(base_patterns) = &base_patterns {
+        updated.extend(check_base_dependencies(
+            &mut service_map,
+            &canon_changed_files,
+            base_patterns,
+            DepPattern::is_match,
+        ));
+    }
 
-    // 4. now gather all services that depend on the services that we already found.
+    // 4. now gather all services that depend on the services that we already found (in the
+    // current or base dependency graph).
     // we repeat this until we find no additional peer dependencies
     loop {
-        updated = check_peer_dependencies(&mut service_map, &updated)?;
+        let mut peers = check_peer_dependencies(&mut service_map, &updated)?;
+        if let Some(base_patterns) = &base_patterns {
+            peers.extend(check_base_dependencies(
+                &mut service_map,
+                &updated,
+                base_patterns,
+                DepPattern::is_matched_by,
+            ));
+        }
+
+        updated = peers;
         if updated.is_empty() {
             break;
         }
@@ -70,6 +124,46 @@ pub fn resolve(
         .collect())
 }
 
+/// Record the changed files inside of every service as well as the changed files that match any
+/// of its (auto-discovered) dependencies.
+fn record_changed_files(
+    services: &mut [Service],
+    changed_files: &[PathInfo],
+    opts: &Opts,
+) -> Result<()> {
+    for changed_file in changed_files {
+        let owner = owner_path(changed_file, opts, |path| {
+            services.iter().any(|svc| svc.path.canonicalized == path)
+        })?;
+
+        if let Some(svc) = owner.and_then(|owner| {
+            services
+                .iter_mut()
+                .find(|svc| svc.path.canonicalized == owner)
+        }) {
+            svc.changed_files.push(changed_file.display_path.clone());
+        }
+    }
+
+    for service in services.iter_mut() {
+        let matching = changed_files.iter().filter(|file| {
+            service
+                .depsfile
+                .dependencies
+                .iter()
+                .any(|dep| dep.is_match(&file.canonicalized))
+                || service
+                    .auto_dependencies
+                    .iter()
+                    .any(|dep| dep.pattern.is_match(&file.canonicalized))
+        });
+
+        service.dependency_files = matching.map(|file| file.display_path.clone()).collect();
+    }
+
+    Ok(())
+}
+
 fn check_direct_dependencies(
     services: &mut HashMap<String, Service>,
     changed_files: &Vec<PathInfo>,
@@ -120,22 +214,81 @@ fn check_peer_dependencies(
     Ok(changed)
 }
 
+/// The dependency patterns of all given `services` in the `base` dependency graph (by
+/// canonicalized service path).
+fn base_patterns(
+    base: &Lockfile,
+    services: &[Service],
+    opts: &Opts,
+) -> Result<HashMap<String, Vec<DepPattern>>> {
+    services
+        .iter()
+        .flat_map(|svc| {
+            base.services
+                .get(&relative_name(&svc.path, opts))
+                .map(|base_service| (svc, base_service))
+        })
+        .map(|(svc, base_service)| {
+            Ok((svc.path.canonicalized.clone(), base_service.patterns(opts)?))
+        })
+        .collect()
+}
+
+/// Trigger all services that are not triggered yet but have a dependency in the base dependency
+/// graph that matches any of the `changed` paths (either changed files or changed services).
+fn check_base_dependencies<F>(
+    services: &mut HashMap<String, Service>,
+    changed: &[PathInfo],
+    base_patterns: &HashMap<String, Vec<DepPattern>>,
+    matches: F,
+) -> Vec<PathInfo>
+where
+    F: Fn(&DepPattern, &str) -> bool,
+{
+    let mut triggered = Vec::new();
+
+    for service in (*services).values_mut() {
+        if service.has_trigger() {
+            continue;
+        }
+
+        let Some(patterns) = base_patterns.get(&service.path.canonicalized) else {
+            continue;
+        };
+
+        let base_dependency = changed.iter().find(|path| {
+            patterns
+                .iter()
+                .any(|pattern| matches(pattern, &path.canonicalized))
+        });
+
+        if let Some(dependency) = base_dependency {
+            triggered.push(service.path.clone());
+            service.trigger(BuildTrigger::BaseDependency(
+                dependency.display_path.clone(),
+            ));
+        }
+    }
+
+    triggered
+}
+
 fn service_has_peer_dependency<'a>(
     service: &Service,
     changed_files: &'a Vec<PathInfo>,
-) -> Option<(&'a PathInfo, bool)> {
+) -> Option<(&'a PathInfo, Option<Language>)> {
     for changed_file in changed_files {
         for dep in &service.depsfile.dependencies {
             if dep.is_matched_by(&changed_file.canonicalized) {
                 // we found _some_ dependency on that service -> return early
-                return Some((changed_file, false));
+                return Some((changed_file, None));
             }
         }
 
         for dep in &service.auto_dependencies {
             if dep.pattern.is_matched_by(&changed_file.canonicalized) {
                 // we found _some_ dependency on that service -> return early
-                return Some((changed_file, true));
+                return Some((changed_file, Some(dep.language.clone())));
             }
         }
     }
@@ -146,19 +299,19 @@ fn service_has_peer_dependency<'a>(
 fn service_has_dependency<'a>(
     service: &Service,
     changed_files: &'a Vec<PathInfo>,
-) -> Option<(&'a PathInfo, bool)> {
+) -> Option<(&'a PathInfo, Option<Language>)> {
     for changed_file in changed_files {
         for dep in &service.depsfile.dependencies {
             if dep.is_match(&changed_file.canonicalized) {
                 // we found _some_ dependency on that service -> return early
-                return Some((changed_file, false));
+                return Some((changed_file, None));
             }
         }
 
         for dep in &service.auto_dependencies {
             if dep.pattern.is_match(&changed_file.canonicalized) {
                 // we found _some_ dependency on that service -> return early
-                return Some((changed_file, true));
+                return Some((changed_file, Some(dep.language.clone())));
             }
         }
     }
@@ -171,16 +324,69 @@ fn check_file_dependency(
     pattern: &PathInfo,
     opts: &Opts,
 ) -> Result<Option<PathInfo>> {
-    let file_path = std::path::PathBuf::from(&pattern.canonicalized);
+    let owner = owner_path(pattern, opts, |path| services.contains_key(path))?;
+
+    Ok(owner.and_then(|path| services.get_mut(&path)).map(|entry| {
+        entry.trigger(BuildTrigger::FileChange);
+        entry.path.clone()
+    }))
+}
+
+/// Changed files grouped by the services they belong to.
+#[derive(Debug, Default, PartialEq)]
+pub struct FileOwnership {
+    /// Changed files (as given) by canonicalized service path, both sorted.
+    pub owned: BTreeMap<String, Vec<String>>,
+    /// Changed files (as given) that do not belong to any of the services, sorted.
+    pub unowned: Vec<String>,
+}
+
+/// Determine which of the `changed_files` belong to which of the given `services`: a file
+/// belongs to the service of the closest parent directory (up to the target directory).
+pub fn ownership(
+    services: &[Service],
+    changed_files: &[String],
+    opts: &Opts,
+) -> Result<FileOwnership> {
+    let mut ownership = FileOwnership::default();
+
+    for file in changed_files {
+        let path = PathInfo::new(file, &opts.target.canonicalized)?;
+        let owner = owner_path(&path, opts, |dir| {
+            services.iter().any(|svc| svc.path.canonicalized == dir)
+        })?;
+
+        match owner {
+            Some(owner) => ownership.owned.entry(owner).or_default().push(file.clone()),
+            None => ownership.unowned.push(file.clone()),
+        }
+    }
+
+    for files in ownership.owned.values_mut() {
+        files.sort();
+        files.dedup();
+    }
+    ownership.unowned.sort();
+    ownership.unowned.dedup();
+
+    Ok(ownership)
+}
+
+/// Canonicalized path of the closest parent directory of `file` that is a service, according to
+/// `is_service`. Only directories up to the target directory are considered.
+fn owner_path<F>(file: &PathInfo, opts: &Opts, is_service: F) -> Result<Option<String>>
+where
+    F: Fn(&str) -> bool,
+{
+    let file_path = std::path::PathBuf::from(&file.canonicalized);
 
     for path in file_path.ancestors().skip(1) {
         let str_path = path
             .to_str()
             .ok_or_else(|| anyhow!("cannot determine parent path for {}", file_path.display()))?;
 
-        if let Some(entry) = services.get_mut(str_path) {
-            entry.trigger(BuildTrigger::FileChange);
-            return Ok(Some(entry.path.clone()));
+        if is_service(str_path) {
+            return Ok(Some(str_path.to_owned()));
         }
 
         // only walk directories until the root project directory
@@ -191,3 +397,203 @@ fn check_file_dependency(
 
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::lock::Lockfile;
+    use crate::service::{BuildTrigger, Service};
+    use crate::test_util::create_file;
+
+    use super::{ownership, resolve, resolve_with_base};
+
+    fn repository() -> Result<TempDir> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "dependencies:\n  - ../api\n")?;
+        Ok(dir)
+    }
+
+    fn triggers(services: Vec<Service>, opts: &Opts) -> Vec<(String, String)> {
+        let mut triggers: Vec<_> = services
+            .into_iter()
+            .map(|svc| {
+                (
+                    crate::lock::relative_name(&svc.path, opts),
+                    svc.trigger.map(|t| t.to_string()).unwrap_or_default(),
+                )
+            })
+            .collect();
+        triggers.sort();
+        triggers
+    }
+
+    #[test]
+    fn resolve_union_with_base() -> Result<()> {
+        let dir = repository()?;
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let base = Lockfile::new(&Service::discover(&opts)?, &opts);
+
+        // remove the dependency of 'api' on 'lib'
+        create_file(dir.path(), "api/Depsfile", "")?;
+        let changed = vec!["lib/lib.go".to_string()];
+
+        // the current graph alone does not include 'api' (and 'web') anymore
+        let current = resolve(Service::discover(&opts)?, changed.clone(), &opts)?;
+        assert_eq!(1, current.len());
+
+        let union = resolve_with_base(Service::discover(&opts)?, changed, &base, &opts)?;
+        let triggers = triggers(union, &opts);
+
+        assert_eq!(3, triggers.len());
+        assert_eq!("api", triggers[0].0);
+        assert!(triggers[0].1.starts_with("Base-Dependency("));
+        assert_eq!(("lib".to_string(), "FileChange".to_string()), triggers[1]);
+        assert_eq!("web", triggers[2].0);
+        assert!(triggers[2].1.starts_with("Peer-Dependency("));
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_removed_file_dependency() -> Result<()> {
+        let dir = repository()?;
+        create_file(dir.path(), "shared/config.yaml", "")?;
+        create_file(
+            dir.path(),
+            "api/Depsfile",
+            "dependencies:\n  - ../lib\n  - ../shared/config.yaml\n",
+        )?;
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let base = Lockfile::new(&Service::discover(&opts)?, &opts);
+
+        // remove the dependency of 'api' on the shared files (that do not belong to any service)
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        let changed = vec!["shared/config.yaml".to_string()];
+
+        assert!(resolve(Service::discover(&opts)?, changed.clone(), &opts)?.is_empty());
+
+        let union = resolve_with_base(Service::discover(&opts)?, changed, &base, &opts)?;
+        let triggers = triggers(union, &opts);
+
+        assert_eq!(2, triggers.len());
+        assert_eq!(
+            (
+                "api".to_string(),
+                "Base-Dependency(shared/config.yaml)".to_string()
+            ),
+            triggers[0]
+        );
+        assert_eq!("web", triggers[1].0);
+        assert!(triggers[1].1.starts_with("Peer-Dependency("));
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_base_trigger() -> Result<()> {
+        let dir = repository()?;
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let base = Lockfile::new(&Service::discover(&opts)?, &opts);
+
+        // without any changes to the graph the base graph does not add anything
+        let union = resolve_with_base(
+            Service::discover(&opts)?,
+            vec!["lib/lib.go".to_string()],
+            &base,
+            &opts,
+        )?;
+
+        assert!(
+            union
+                .iter()
+                .all(|svc| !matches!(svc.trigger, Some(BuildTrigger::BaseDependency(_))))
+        );
+        assert_eq!(3, union.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn changed_file_ownership() -> Result<()> {
+        let dir = repository()?;
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let services = Service::discover(&opts)?;
+
+        let changed = vec![
+            "web/index.ts".to_string(),
+            "README.md".to_string(),
+            "lib/b.go".to_string(),
+            "lib/a.go".to_string(),
+            "lib/a.go".to_string(),
+        ];
+        let ownership = ownership(&services, &changed, &opts)?;
+
+        let owned: Vec<_> = ownership
+            .owned
+            .iter()
+            .map(|(path, files)| (path.rsplit('/').next().unwrap_or_default(), files.clone()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("lib", vec!["lib/a.go".to_string(), "lib/b.go".to_string()]),
+                ("web", vec!["web/index.ts".to_string()]),
+            ],
+            owned
+        );
+        assert_eq!(vec!["README.md".to_string()], ownership.unowned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_changed_files() -> Result<()> {
+        let dir = repository()?;
+        let opts = Opts::new(dir.path(), Config::default())?;
+
+        let changed = vec![
+            "lib/b.go".to_string(),
+            "api/main.go".to_string(),
+            "lib/a.go".to_string(),
+            "README.md".to_string(),
+        ];
+        let mut resolved = resolve(Service::discover(&opts)?, changed, &opts)?;
+        resolved.sort_by(|a, b| a.path.canonicalized.cmp(&b.path.canonicalized));
+
+        let files: Vec<_> = resolved
+            .iter()
+            .map(|svc| {
+                (
+                    crate::lock::relative_name(&svc.path, &opts),
+                    svc.changed_files.clone(),
+                    svc.dependency_files.clone(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                (
+                    "api".to_string(),
+                    vec!["api/main.go".to_string()],
+                    vec!["lib/a.go".to_string(), "lib/b.go".to_string()]
+                ),
+                (
+                    "lib".to_string(),
+                    vec!["lib/a.go".to_string(), "lib/b.go".to_string()],
+                    vec![]
+                ),
+                ("web".to_string(), vec![], vec!["api/main.go".to_string()]),
+            ],
+            files
+        );
+
+        Ok(())
+    }
+}
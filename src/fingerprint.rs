@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+
+use crate::cache::content_hash;
+use crate::cli::{Opts, OutputFormat};
+use crate::closure;
+use crate::graph::{self, Graph};
+use crate::lock::relative_name;
+use crate::service::Service;
+
+/// Bump this version whenever the way fingerprints are calculated changes.
+const FINGERPRINT_VERSION: &str = "1";
+
+/// Determine the fingerprints of all given (discovered) services by canonicalized service path.
+///
+/// The fingerprint of a service is the SHA-256 hash of the content of its input closure (see
+/// `closure::files`), the relevant configuration and the fingerprints of all services it depends
+/// on. All paths are relative to the target directory, so that the fingerprint does not depend on
+/// the location of the repository.
+pub fn fingerprints(services: Vec<Service>, opts: &Opts) -> Result<HashMap<String, String>> {
+    let graph = Graph::new(services);
+    let mut fingerprinter = Fingerprinter::new(&graph, opts);
+
+    let mut fingerprints = HashMap::new();
+    for node in &graph.nodes {
+        let fingerprint = fingerprinter.fingerprint(&node.id)?;
+        fingerprints.insert(node.service.path.canonicalized.clone(), fingerprint);
+    }
+
+    Ok(fingerprints)
+}
+
+/// Write the fingerprint of the given `service` (name or path relative to the target directory)
+/// or of all services if none is given.
+pub fn output<W>(service: Option<&str>, opts: &Opts, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let graph = Graph::new(Service::discover(opts)?);
+    let mut fingerprinter = Fingerprinter::new(&graph, opts);
+
+    let nodes: Vec<_> = match service {
+        Some(service) => vec![
+            graph
+                .find(service, opts)
+                .ok_or_else(|| anyhow!("unknown service '{service}'"))?,
+        ],
+        None => graph.nodes.iter().collect(),
+    };
+
+    let mut fingerprints = BTreeMap::new();
+    for node in nodes {
+        let fingerprint = fingerprinter.fingerprint(&node.id)?;
+        fingerprints.insert(relative_name(&node.service.path, opts), fingerprint);
+    }
+
+    match (&opts.output, service) {
+        (OutputFormat::Json, _) => serde_json::to_writer(&mut *w, &fingerprints)?,
+        (_, Some(_)) => {
+            for fingerprint in fingerprints.values() {
+                writeln!(w, "{fingerprint}")?;
+            }
+        }
+        (_, None) => {
+            for (name, fingerprint) in fingerprints {
+                writeln!(w, "{fingerprint}  {name}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct Fingerprinter<'a> {
+    graph: &'a Graph,
+    opts: &'a Opts,
+    /// All files that may be part of any input closure.
+    candidates: Vec<String>,
+    /// Dependency cycles (see `Graph::components`) by node id, sorted by service path.
+    components: HashMap<String, Vec<(String, String)>>,
+    /// Content hashes by file path.
+    hashes: HashMap<String, String>,
+    /// Fingerprints by node id.
+    fingerprints: HashMap<String, String>,
+}
+
+impl<'a> Fingerprinter<'a> {
+    fn new(graph: &'a Graph, opts: &'a Opts) -> Self {
+        let services: Vec<_> = graph.nodes.iter().map(|node| &node.service).collect();
+        let candidates = closure::candidates(&closure::patterns(&services, opts), opts);
+
+        let mut components = HashMap::new();
+        for component in graph.components() {
+            let mut members: Vec<_> = graph
+                .nodes
+                .iter()
+                .filter(|node| component.contains(&node.id.as_str()))
+                .map(|node| (relative_name(&node.service.path, opts), node.id.clone()))
+                .collect();
+            members.sort();
+
+            for (_, id) in &members {
+                components.insert(id.clone(), members.clone());
+            }
+        }
+
+        Self {
+            graph,
+            opts,
+            candidates,
+            components,
+            hashes: HashMap::new(),
+            fingerprints: HashMap::new(),
+        }
+    }
+
+    /// Fingerprint of the node with the given `id`.
+    ///
+    /// The services of a dependency cycle are fingerprinted as a unit (sorted by path), so that
+    /// the fingerprint of every service of the cycle does not depend on the service the
+    /// calculation started with.
+    fn fingerprint(&mut self, id: &str) -> Result<String> {
+        if let Some(fingerprint) = self.fingerprints.get(id) {
+            return Ok(fingerprint.clone());
+        }
+
+        let members = self
+            .components
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown service node '{id}'"))?;
+
+        let mut hashes = Vec::new();
+        for (name, member) in &members {
+            hashes.push((name, member, self.service_hash(member, &members)?));
+        }
+
+        if let [(_, member, hash)] = hashes.as_slice() {
+            self.fingerprints.insert((*member).clone(), hash.clone());
+        } else {
+            let mut hasher = Sha256::new();
+            hasher.update(format!("monodeps fingerprint v{FINGERPRINT_VERSION}\n"));
+            for (name, _, hash) in &hashes {
+                hasher.update(format!("cycle {name} {hash}\n"));
+            }
+            let cycle = format!("{:x}", hasher.finalize());
+
+            for (name, member, _) in hashes {
+                let fingerprint = Sha256::digest(format!("{cycle}\nservice {name}\n"));
+                self.fingerprints
+                    .insert(member.clone(), format!("{fingerprint:x}"));
+            }
+        }
+
+        Ok(self.fingerprints[id].clone())
+    }
+
+    /// Hash of the service node with the given `id` covering its input closure, the relevant
+    /// configuration and the fingerprints of all services it depends on, except for the ones of
+    /// the same dependency cycle (`members`).
+    fn service_hash(&mut self, id: &str, members: &[(String, String)]) -> Result<String> {
+        let (graph, opts) = (self.graph, self.opts);
+        let node = graph
+            .nodes
+            .iter()
+            .find(|node| node.id == id)
+            .ok_or_else(|| anyhow!("unknown service node '{id}'"))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("monodeps fingerprint v{FINGERPRINT_VERSION}\n"));
+
+        // relevant configuration
+        hasher.update(format!("{:?}\n", opts.config.auto_discovery));
+        for global in &opts.config.global_dependencies {
+            hasher.update(format!("global {}\n", graph::pattern_label(global, opts)));
+        }
+
+        // own files and dependency files
+        let services = [&node.service];
+        let patterns = closure::patterns(&services, opts);
+        for file in closure::matching(&self.candidates, &services, &patterns) {
+            let hash = match self.hashes.get(&file) {
+                Some(hash) => hash.clone(),
+                None => {
+                    let hash = content_hash(&file)?;
+                    self.hashes.insert(file.clone(), hash.clone());
+                    hash
+                }
+            };
+            hasher.update(format!("file {} {hash}\n", self.relative(&file)));
+        }
+
+        // services the service depends on
+        let mut dependencies: Vec<_> = graph
+            .edges_of(id)
+            .filter_map(|edge| graph.nodes.iter().find(|node| node.id == edge.to))
+            .map(|node| (relative_name(&node.service.path, opts), node.id.clone()))
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+
+        for (name, dependency) in dependencies {
+            if members.iter().any(|(_, member)| *member == dependency) {
+                hasher.update(format!("cycle {name}\n"));
+                continue;
+            }
+
+            let fingerprint = self.fingerprint(&dependency)?;
+            hasher.update(format!("service {name} {fingerprint}\n"));
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Path of the given file relative to the target directory (if inside of it).
+    fn relative(&self, file: &str) -> String {
+        Path::new(file)
+            .strip_prefix(&self.opts.target.canonicalized)
+            .map(|relative| relative.display().to_string())
+            .unwrap_or_else(|_| file.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::graph::Graph;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{Fingerprinter, fingerprints};
+
+    fn repository() -> Result<TempDir> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "lib/lib.go", "package lib")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "api/main.go", "package main")?;
+        create_file(dir.path(), "web/Depsfile", "")?;
+        create_file(dir.path(), "web/index.ts", "export {}")?;
+        Ok(dir)
+    }
+
+    /// Fingerprints of `lib`, `api` and `web` in this order.
+    fn fingerprinted(dir: &Path) -> Result<Vec<String>> {
+        let opts = Opts::new(dir, Config::default())?;
+        let fingerprints = fingerprints(Service::discover(&opts)?, &opts)?;
+
+        ["lib", "api", "web"]
+            .iter()
+            .map(|name| {
+                let path = dir.canonicalize()?.join(name);
+                fingerprints
+                    .get(path.to_str().unwrap_or_default())
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("missing fingerprint of {name}"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fingerprint_deterministic() -> Result<()> {
+        let first = repository()?;
+        let second = repository()?;
+
+        // independent of the location of the repository
+        assert_eq!(fingerprinted(first.path())?, fingerprinted(second.path())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_changes() -> Result<()> {
+        let dir = repository()?;
+        let before = fingerprinted(dir.path())?;
+
+        create_file(dir.path(), "lib/lib.go", "package lib // changed")?;
+        let after = fingerprinted(dir.path())?;
+
+        // 'lib' and its dependent 'api' change, 'web' does not
+        assert_ne!(before[0], after[0]);
+        assert_ne!(before[1], after[1]);
+        assert_eq!(before[2], after[2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_hidden_files() -> Result<()> {
+        let dir = repository()?;
+        create_file(dir.path(), "api/.npmrc", "registry=https://a.example.com\n")?;
+        let before = fingerprinted(dir.path())?;
+
+        create_file(dir.path(), "api/.npmrc", "registry=https://b.example.com\n")?;
+        let after = fingerprinted(dir.path())?;
+
+        assert_eq!(before[0], after[0]);
+        assert_ne!(before[1], after[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_cycle() -> Result<()> {
+        let dir = repository()?;
+        create_file(dir.path(), "lib/Depsfile", "dependencies:\n  - ../api\n")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let graph = Graph::new(Service::discover(&opts)?);
+        let id = |name: &str| {
+            graph
+                .find(name, &opts)
+                .map(|node| node.id.clone())
+                .unwrap_or_default()
+        };
+        let (lib, api) = (id("lib"), id("api"));
+
+        // the fingerprints do not depend on the service the calculation starts with
+        let mut from_lib = Fingerprinter::new(&graph, &opts);
+        let lib_first = (from_lib.fingerprint(&lib)?, from_lib.fingerprint(&api)?);
+
+        let mut from_api = Fingerprinter::new(&graph, &opts);
+        let api_first = (from_api.fingerprint(&api)?, from_api.fingerprint(&lib)?);
+
+        assert_eq!(lib_first, (api_first.1.clone(), api_first.0.clone()));
+        assert_ne!(lib_first.0, lib_first.1);
+
+        // and every service of the cycle changes with any of them
+        let before = fingerprinted(dir.path())?;
+        create_file(dir.path(), "api/main.go", "package main // changed")?;
+        let after = fingerprinted(dir.path())?;
+
+        assert_ne!(before[0], after[0]);
+        assert_ne!(before[1], after[1]);
+        assert_eq!(before[2], after[2]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Result, anyhow, bail};
+
+/// A temporary git worktree with a specific revision checked out. The worktree is removed again
+/// as soon as the value is dropped.
+pub struct Worktree {
+    repository: PathBuf,
+    dir: PathBuf,
+    target: PathBuf,
+}
+
+impl Worktree {
+    /// Check out the given `revision` of the git repository containing the `target` directory
+    /// into a temporary worktree.
+    pub fn checkout<P>(target: P, revision: &str) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let target = target.as_ref();
+        let repository = toplevel(target)?;
+        let prefix = prefix(target, &repository)?;
+        let commit = commit(&repository, revision)?;
+
+        let dir = std::env::temp_dir().join(format!(
+            "monodeps-{}-{}",
+            std::process::id(),
+            &commit[..commit.len().min(12)]
+        ));
+
+        let dir_str = dir
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid temporary directory {}", dir.display()))?;
+        git(
+            &repository,
+            &["worktree", "add", "--detach", "--force", dir_str, &commit],
+        )?;
+
+        log::debug!("checked out '{revision}' ({commit}) into {dir_str}");
+
+        Ok(Self {
+            target: dir.join(prefix),
+            repository,
+            dir,
+        })
+    }
+
+    /// The target directory inside of the checked out worktree.
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        let removed = self
+            .dir
+            .to_str()
+            .map(|dir| git(&self.repository, &["worktree", "remove", "--force", dir]).is_ok())
+            .unwrap_or(false);
+
+        if !removed {
+            _ = std::fs::remove_dir_all(&self.dir);
+            _ = git(&self.repository, &["worktree", "prune"]);
+        }
+    }
+}
+
+/// Root directory of the git repository containing the directory `dir`.
+pub fn toplevel<P>(dir: P) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    Ok(PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?))
+}
+
+/// Commit hash of the given `revision` in the git repository containing the directory `dir`.
+pub fn commit<P>(dir: P, revision: &str) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    git(
+        dir,
+        &["rev-parse", "--verify", &format!("{revision}^{{commit}}")],
+    )
+    .map_err(|_| anyhow!("unknown git revision '{revision}'"))
+}
+
+/// All files that changed between the given `revision` and `HEAD` in the git repository
+/// containing the directory `dir`. The paths are relative to `dir`, even for files outside of it.
+pub fn changed_files<P>(dir: P, revision: &str) -> Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let repository = toplevel(dir)?;
+    let commit = commit(&repository, revision)?;
+
+    // the paths of git are relative to the repository root
+    let prefix = prefix(dir, &repository)?;
+    let up: PathBuf = prefix.components().map(|_| "..").collect();
+
+    let output = git(
+        &repository,
+        &["diff", "--name-only", "--no-renames", "-z", &commit, "HEAD"],
+    )?;
+
+    Ok(output
+        .split('\0')
+        .filter(|file| !file.is_empty())
+        .map(|file| match Path::new(file).strip_prefix(&prefix) {
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => up.join(file).display().to_string(),
+        })
+        .collect())
+}
+
+/// Path of the directory `dir` relative to the root directory of its git `repository`.
+fn prefix(dir: &Path, repository: &Path) -> Result<PathBuf> {
+    // the directory may be any sub directory of the repository
+    Ok(dir
+        .canonicalize()?
+        .strip_prefix(repository.canonicalize()?)
+        .map(|prefix| prefix.to_path_buf())
+        .unwrap_or_default())
+}
+
+/// Run git with the given arguments in the directory `dir` and return its trimmed output.
+fn git<P>(dir: P, args: &[&str]) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|err| anyhow!("failed to run git: {err}"))?;
+
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::test_util::git;
+
+    use super::{Worktree, changed_files};
+
+    fn repository() -> Result<TempDir> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        git(dir.path(), &["init", "-q"])?;
+        std::fs::create_dir_all(dir.path().join("sub"))?;
+        std::fs::write(dir.path().join("sub/file"), "first")?;
+        git(dir.path(), &["add", "-A"])?;
+        git(dir.path(), &["commit", "-q", "-m", "first"])?;
+        std::fs::write(dir.path().join("sub/file"), "second")?;
+        git(dir.path(), &["commit", "-q", "-a", "-m", "second"])?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn checkout_revision() -> Result<()> {
+        let dir = repository()?;
+        let worktree = Worktree::checkout(dir.path().join("sub"), "HEAD~1")?;
+        let checked_out = worktree.target().to_path_buf();
+
+        assert_eq!("first", std::fs::read_to_string(checked_out.join("file"))?);
+
+        drop(worktree);
+        assert!(!checked_out.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn changed_files_since_revision() -> Result<()> {
+        let dir = repository()?;
+        std::fs::write(dir.path().join("other"), "")?;
+        git(dir.path(), &["add", "-A"])?;
+        git(dir.path(), &["commit", "-q", "-m", "third"])?;
+
+        assert_eq!(vec!["other"], changed_files(dir.path(), "HEAD~1")?);
+        assert_eq!(
+            vec!["../other", "file"],
+            changed_files(dir.path().join("sub"), "HEAD~2")?
+        );
+        assert!(changed_files(dir.path(), "HEAD")?.is_empty());
+        assert!(changed_files(dir.path(), "does-not-exist").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn checkout_unknown_revision() -> Result<()> {
+        let dir = repository()?;
+
+        assert!(Worktree::checkout(dir.path(), "does-not-exist").is_err());
+
+        Ok(())
+    }
+}
@@ -1,57 +1,574 @@
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use crate::cli::Opts;
-use crate::config::DepPattern;
-use crate::service::{self, Service};
-use anyhow::Result;
+use crate::cli::{Direction, GraphOptions, Opts, OutputFormat};
+use crate::config::{DepPattern, Language};
+use crate::dependency;
+use crate::lock::{self, EdgeKind, Lockfile};
+use crate::path::PathInfo;
+use crate::service::{self, BuildTrigger, Service};
+use anyhow::{Result, bail};
 
-pub fn calculate(opts: Opts) -> Result<()> {
-    let services = service::Service::discover(&opts)?;
-    let uniques = unique_service_names(services);
+mod dot;
+mod graphml;
+mod json;
+mod mermaid;
+mod plantuml;
 
-    println!("// Auto-generated by monodeps [https://github.com/kongo2002/monodeps]");
-    println!();
-    println!("digraph dependencies {{");
+/// Intermediate model of the service dependency graph that is rendered by the various output
+/// formats.
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// Dependencies (files, globs or regular expressions) that do not point into any service.
+    pub patterns: Vec<PatternNode>,
+    /// Edges of services to the dependencies in `patterns`.
+    pub pattern_edges: Vec<Edge>,
+}
 
-    for (ident, svc) in &uniques {
-        println!("  {} [label=\"{}\"];", ident.id, ident.name);
+/// A service in the dependency graph.
+pub struct Node {
+    /// Identifier of the node that is unique in the graph (e.g. `service_1`).
+    pub id: String,
+    /// Short but unique name of the service (see `unique_service_names`).
+    pub name: String,
+    pub service: Service,
+    /// Canonicalized path of the dependency that caused the build trigger of the service, if any
+    /// (see `Graph::mark_affected`).
+    pub trigger_source: Option<String>,
+}
 
-        let deps = svc
-            .auto_dependencies
-            .iter()
-            .map(|auto| &auto.pattern)
-            .chain(svc.depsfile.dependencies.iter());
+/// A dependency that does not point into any service.
+pub struct PatternNode {
+    /// Identifier of the node that is unique in the graph (e.g. `pattern_1`).
+    pub id: String,
+    pub pattern: DepPattern,
+}
+
+/// A dependency of one service (`from`) on another one (`to`), both referenced by node id.
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+    pub language: Option<Language>,
+    /// The dependency pattern the edge originates from.
+    pub pattern: DepPattern,
+}
+
+impl Graph {
+    /// Build the dependency graph of the given list of services. Every dependency that points
+    /// into another service results in an edge. All remaining dependencies are collected as
+    /// pattern nodes.
+    pub fn new(mut services: Vec<Service>) -> Self {
+        services.sort_by(|a, b| a.path.canonicalized.cmp(&b.path.canonicalized));
+
+        let nodes: Vec<_> = unique_service_names(services)
+            .into_iter()
+            .map(|(ident, service)| Node {
+                id: ident.id,
+                name: ident.name,
+                service,
+                trigger_source: None,
+            })
+            .collect();
+
+        let mut edges = Vec::new();
+        let mut patterns: Vec<PatternNode> = Vec::new();
+        let mut pattern_edges = Vec::new();
+
+        for node in &nodes {
+            let explicit = node
+                .service
+                .depsfile
+                .dependencies
+                .iter()
+                .map(|pattern| (pattern, EdgeKind::Explicit, None));
+            let auto = node
+                .service
+                .auto_dependencies
+                .iter()
+                .map(|auto| (&auto.pattern, EdgeKind::Auto, Some(auto.language.clone())));
+
+            for (pattern, kind, language) in explicit.chain(auto) {
+                match find_dependent_service(pattern, &nodes) {
+                    Some(dependency) if dependency.id != node.id => edges.push(Edge {
+                        from: node.id.clone(),
+                        to: dependency.id.clone(),
+                        kind,
+                        language,
+                        pattern: pattern.clone(),
+                    }),
+                    Some(_) => {}
+                    None => {
+                        let label = pattern.to_string();
+                        let id = match patterns.iter().find(|p| p.pattern.to_string() == label) {
+                            Some(existing) => existing.id.clone(),
+                            None => {
+                                let id = format!("pattern_{}", patterns.len() + 1);
+                                patterns.push(PatternNode {
+                                    id: id.clone(),
+                                    pattern: pattern.clone(),
+                                });
+                                id
+                            }
+                        };
+
+                        pattern_edges.push(Edge {
+                            from: node.id.clone(),
+                            to: id,
+                            kind,
+                            language,
+                            pattern: pattern.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Self {
+            nodes,
+            edges,
+            patterns,
+            pattern_edges,
+        }
+    }
+
+    /// All distinct pairs of dependent nodes (`from` -> `to`) in order of appearance. If a service
+    /// depends on another one both explicitly and via auto-discovery, the explicit edge is
+    /// returned.
+    pub fn service_edges(&self) -> Vec<&Edge> {
+        self.distinct(&self.edges)
+    }
+
+    /// All distinct edges of services to pattern nodes (see `service_edges`).
+    pub fn distinct_pattern_edges(&self) -> Vec<&Edge> {
+        self.distinct(&self.pattern_edges)
+    }
 
+    fn distinct<'a>(&self, edges: &'a [Edge]) -> Vec<&'a Edge> {
         let mut seen = HashSet::new();
+        let explicit = edges.iter().filter(|edge| edge.kind == EdgeKind::Explicit);
+        let auto = edges.iter().filter(|edge| edge.kind == EdgeKind::Auto);
+
+        let mut edges: Vec<_> = explicit
+            .chain(auto)
+            .filter(|edge| seen.insert((&edge.from, &edge.to)))
+            .collect();
+
+        edges.sort_by_key(|edge| (self.position(&edge.from), self.position(&edge.to)));
+        edges
+    }
+
+    /// Find the node of the service given by either its (short) name or its path relative to the
+    /// target directory.
+    pub fn find(&self, service: &str, opts: &Opts) -> Option<&Node> {
+        let path = service.trim_start_matches("./").trim_end_matches('/');
+        let path = if path.is_empty() { "." } else { path };
+
+        self.nodes.iter().find(|node| {
+            node.name == service || lock::relative_name(&node.service.path, opts) == path
+        })
+    }
+
+    /// Reduce the graph to the neighbourhood of the node with the given `id`, i.e. all services
+    /// that are reachable within `depth` edges (unlimited if `None`) in the given `direction`.
+    /// `Direction::Down` follows the dependencies of the service, `Direction::Up` its dependents.
+    pub fn focus(self, id: &str, depth: Option<usize>, direction: Direction) -> Self {
+        let mut distances = HashMap::from([(id.to_owned(), 0usize)]);
+        let mut queue = VecDeque::from([id.to_owned()]);
+
+        while let Some(current) = queue.pop_front() {
+            let distance = distances[&current];
+            if depth.is_some_and(|depth| distance >= depth) {
+                continue;
+            }
+
+            for edge in &self.edges {
+                let next = match direction {
+                    Direction::Down if edge.from == current => &edge.to,
+                    Direction::Up if edge.to == current => &edge.from,
+                    Direction::Both if edge.from == current => &edge.to,
+                    Direction::Both if edge.to == current => &edge.from,
+                    _ => continue,
+                };
+
+                if !distances.contains_key(next) {
+                    distances.insert(next.clone(), distance + 1);
+                    queue.push_back(next.clone());
+                }
+            }
+        }
+
+        self.retain(|id| distances.contains_key(id))
+    }
+
+    /// Keep the nodes whose id satisfies the given predicate, including all edges between them,
+    /// and the pattern nodes the remaining services depend on.
+    fn retain<P>(self, predicate: P) -> Self
+    where
+        P: Fn(&str) -> bool,
+    {
+        let nodes: Vec<_> = self
+            .nodes
+            .into_iter()
+            .filter(|node| predicate(&node.id))
+            .collect();
+        let edges = self
+            .edges
+            .into_iter()
+            .filter(|edge| predicate(&edge.from) && predicate(&edge.to))
+            .collect();
+        let pattern_edges: Vec<_> = self
+            .pattern_edges
+            .into_iter()
+            .filter(|edge| predicate(&edge.from))
+            .collect();
+        let patterns = self
+            .patterns
+            .into_iter()
+            .filter(|pattern| pattern_edges.iter().any(|edge| edge.to == pattern.id))
+            .collect();
+
+        Self {
+            nodes,
+            edges,
+            patterns,
+            pattern_edges,
+        }
+    }
+
+    /// Mark all nodes of the given `affected` services (as determined by `dependency::resolve`)
+    /// with their `BuildTrigger`.
+    pub fn mark_affected(&mut self, affected: Vec<Service>, opts: &Opts) {
+        let mut triggers: HashMap<_, _> = affected
+            .into_iter()
+            .map(|svc| (svc.path.canonicalized, svc.trigger))
+            .collect();
+
+        for node in &mut self.nodes {
+            node.service.trigger = triggers.remove(&node.service.path.canonicalized).flatten();
+            node.trigger_source = node
+                .service
+                .trigger
+                .as_ref()
+                .and_then(|trigger| trigger.source())
+                .and_then(|source| PathInfo::new(source, &opts.target.canonicalized).ok())
+                .map(|source| source.canonicalized);
+        }
+    }
+
+    /// Whether the build trigger propagated along the given edge, meaning both services are
+    /// affected and the dependent service was triggered via the dependency of this very edge,
+    /// i.e. by the service the edge points to or by a changed file of it matching the edge's
+    /// dependency pattern.
+    pub fn propagated(&self, edge: &Edge) -> bool {
+        let node = |id: &str| self.nodes.iter().find(|node| node.id == id);
+
+        let (Some(from), Some(to)) = (node(&edge.from), node(&edge.to)) else {
+            return false;
+        };
+        if !to.is_affected() {
+            return false;
+        }
+
+        let Some(source) = &from.trigger_source else {
+            return false;
+        };
+        if !matches!(
+            from.service.trigger,
+            Some(
+                BuildTrigger::Dependency(..)
+                    | BuildTrigger::PeerDependency(..)
+                    | BuildTrigger::BaseDependency(_)
+            )
+        ) {
+            return false;
+        }
+
+        let to_path = &to.service.path.canonicalized;
+        if source == to_path {
+            // triggered by the dependent service itself
+            edge.pattern.is_matched_by(source)
+        } else {
+            // triggered by a changed file inside of the dependent service
+            Path::new(source).starts_with(to_path) && edge.pattern.is_match(source)
+        }
+    }
+
+    /// All distinct edges originating from the node with the given `id`.
+    pub fn edges_of<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a Edge> + 'a {
+        self.service_edges()
+            .into_iter()
+            .filter(move |edge| edge.from == id)
+    }
 
-        for pattern in deps {
-            if let Some(dependency) = find_dependent_service(pattern, &uniques) {
-                if !seen.insert(dependency.id.clone()) {
-                    continue;
+    /// The strongly connected components of the service nodes (i.e. dependency cycles) as lists
+    /// of node ids. Nodes that are not part of any cycle form a component of their own. The
+    /// components are ordered such that every component comes after all components it depends
+    /// on.
+    pub(crate) fn components(&self) -> Vec<Vec<&str>> {
+        #[derive(Default)]
+        struct State<'a> {
+            index: HashMap<&'a str, usize>,
+            low: HashMap<&'a str, usize>,
+            stack: Vec<&'a str>,
+            on_stack: HashSet<&'a str>,
+            components: Vec<Vec<&'a str>>,
+        }
+
+        // Tarjan's algorithm
+        fn visit<'a>(graph: &'a Graph, id: &'a str, state: &mut State<'a>) {
+            let index = state.index.len();
+            state.index.insert(id, index);
+            state.low.insert(id, index);
+            state.stack.push(id);
+            state.on_stack.insert(id);
+
+            for edge in graph.edges_of(id) {
+                let to = edge.to.as_str();
+                if !state.index.contains_key(to) {
+                    visit(graph, to, state);
+                    let low = state.low[id].min(state.low[to]);
+                    state.low.insert(id, low);
+                } else if state.on_stack.contains(to) {
+                    let low = state.low[id].min(state.index[to]);
+                    state.low.insert(id, low);
+                }
+            }
+
+            if state.low[id] == state.index[id] {
+                let mut component = Vec::new();
+                while let Some(member) = state.stack.pop() {
+                    state.on_stack.remove(member);
+                    component.push(member);
+                    if member == id {
+                        break;
+                    }
                 }
-                println!("  {} -> {};", ident.id, dependency.id);
+                state.components.push(component);
             }
         }
-        println!();
+
+        let mut state = State::default();
+        for node in &self.nodes {
+            if !state.index.contains_key(node.id.as_str()) {
+                visit(self, &node.id, &mut state);
+            }
+        }
+
+        state.components
+    }
+
+    fn position(&self, id: &str) -> usize {
+        self.nodes
+            .iter()
+            .position(|node| node.id == id)
+            .or_else(|| {
+                self.patterns
+                    .iter()
+                    .position(|pattern| pattern.id == id)
+                    .map(|idx| self.nodes.len() + idx)
+            })
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// The pending service nodes of a `Graph` in dependency order: a node is ready as soon as all
+/// nodes it depends on are finished. Nodes are ordered by id and dependency cycles are broken at
+/// the smallest pending id (see `Schedule::cycle_start`).
+pub(crate) struct Schedule<'a> {
+    /// The pending nodes and the (unfinished) nodes they depend on.
+    pending: BTreeMap<&'a str, BTreeSet<&'a str>>,
+}
+
+impl<'a> Schedule<'a> {
+    pub(crate) fn new(graph: &'a Graph) -> Self {
+        let pending = graph
+            .nodes
+            .iter()
+            .map(|node| {
+                let dependencies = graph
+                    .edges_of(&node.id)
+                    .map(|edge| edge.to.as_str())
+                    .collect();
+                (node.id.as_str(), dependencies)
+            })
+            .collect();
+
+        Self { pending }
     }
 
-    println!("}}");
+    /// All pending nodes alongside the unfinished nodes they depend on, ordered by id.
+    pub(crate) fn pending(&self) -> impl Iterator<Item = (&'a str, &BTreeSet<&'a str>)> {
+        self.pending
+            .iter()
+            .map(|(id, dependencies)| (*id, dependencies))
+    }
+
+    /// All pending nodes whose dependencies are finished, ordered by id.
+    pub(crate) fn ready(&self) -> Vec<&'a str> {
+        self.pending()
+            .filter(|(_, dependencies)| dependencies.is_empty())
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// The node a dependency cycle is broken at if no node is ready: the smallest pending id.
+    pub(crate) fn cycle_start(&self) -> Option<&'a str> {
+        self.pending.keys().next().copied()
+    }
+
+    /// Remove the node with the given `id` from the pending nodes (e.g. once it was started).
+    pub(crate) fn remove(&mut self, id: &str) {
+        self.pending.remove(id);
+    }
+
+    /// Mark the node with the given `id` as finished, so nodes depending on it may become ready.
+    pub(crate) fn finish(&mut self, id: &str) {
+        for dependencies in self.pending.values_mut() {
+            dependencies.remove(id);
+        }
+    }
+}
+
+/// Ids of all nodes of the graph in topological order, meaning dependencies first (see
+/// `Schedule`).
+pub(crate) fn topological_order(graph: &Graph) -> Vec<&str> {
+    let mut schedule = Schedule::new(graph);
+    let mut order = Vec::new();
+
+    while let Some(next) = schedule
+        .ready()
+        .first()
+        .copied()
+        .or_else(|| schedule.cycle_start())
+    {
+        schedule.remove(next);
+        schedule.finish(next);
+        order.push(next);
+    }
+
+    order
+}
+
+impl Node {
+    /// Whether the service was marked as affected (see `Graph::mark_affected`).
+    pub fn is_affected(&self) -> bool {
+        self.service.trigger.is_some()
+    }
+
+    /// All languages of the service (see `Service::languages`).
+    pub fn languages(&self) -> Vec<&Language> {
+        self.service.languages()
+    }
+}
+
+/// Label of a dependency pattern: file paths are shown relative to the target directory, glob
+/// and regex patterns as they are.
+pub fn pattern_label(pattern: &DepPattern, opts: &Opts) -> String {
+    let label = pattern.to_string();
+
+    match Path::new(&label).strip_prefix(&opts.target.canonicalized) {
+        Ok(relative) if relative.as_os_str().is_empty() => ".".to_owned(),
+        Ok(relative) => relative.to_string_lossy().into_owned(),
+        Err(_) => label,
+    }
+}
+
+/// Render the given `graph` in the output format of the given `Opts`.
+pub fn render<W>(graph: &Graph, opts: &Opts, options: &GraphOptions, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    match opts.output {
+        // dot is the default graph format
+        OutputFormat::Plain | OutputFormat::Dot => dot::render(graph, opts, options, w)?,
+        OutputFormat::Mermaid => mermaid::render(graph, w)?,
+        OutputFormat::PlantUml => plantuml::render(graph, w)?,
+        OutputFormat::Json => json::render(graph, opts, w)?,
+        OutputFormat::GraphMl => graphml::render(graph, opts, w)?,
+        OutputFormat::Yaml
+        | OutputFormat::GitHubMatrix
+        | OutputFormat::GitLab
+        | OutputFormat::Buildkite
+        | OutputFormat::Markdown
+        | OutputFormat::Template => {
+            bail!(
+                "output format '{}' is not supported by 'graph'",
+                opts.output
+            )
+        }
+    }
 
     Ok(())
 }
 
-fn find_dependent_service<'a>(
-    pattern: &DepPattern,
-    others: &'a HashMap<ServiceIdent, Service>,
-) -> Option<&'a ServiceIdent> {
-    for (ident, svc) in others {
-        if pattern.is_child_of(&svc.path.canonicalized) {
-            return Some(ident);
+/// Discover the services of the target directory and build their dependency graph. With
+/// `changed_files` the services affected by these files are marked (see `Graph::mark_affected`).
+pub fn build(opts: &Opts, changed_files: Option<Vec<String>>) -> Result<Graph> {
+    let services = service::Service::discover(opts)?;
+    let mut graph = Graph::new(services.clone());
+
+    if let Some(changed_files) = changed_files {
+        graph.mark_affected(dependency::resolve(services, changed_files, opts)?, opts);
+    }
+
+    Ok(graph)
+}
+
+/// Render the service graph of the target directory.
+///
+/// With `changed_files` the services affected by these files are highlighted. If a service to
+/// focus is given via `options`, only its neighbourhood is rendered.
+pub fn calculate(
+    opts: Opts,
+    options: &GraphOptions,
+    changed_files: Option<Vec<String>>,
+) -> Result<()> {
+    let mut graph = build(&opts, changed_files)?;
+
+    if let Some(focus) = &options.focus {
+        let id = match graph.find(focus, &opts) {
+            Some(node) => node.id.clone(),
+            None => bail!("unknown service '{focus}'"),
+        };
+        graph = graph.focus(&id, options.depth, options.direction);
+    }
+
+    render(&graph, &opts, options, &mut std::io::stdout().lock())
+}
+
+/// Compare the service graph of the target directory with the one at the `base` git revision.
+///
+/// Depending on the output format, either all added/removed services and edges are listed in
+/// plaintext or the union of both graphs is printed in dot format, with added nodes/edges colored
+/// in green and removed ones in red.
+pub fn diff(base: &str, opts: Opts) -> Result<()> {
+    let services = service::Service::discover(&opts)?;
+    let current = Lockfile::new(&services, &opts);
+    let previous = Lockfile::at_revision(base, &opts)?;
+
+    match opts.output {
+        OutputFormat::Plain => {
+            for drift in previous.diff(&current) {
+                println!("{drift}");
+            }
         }
+        OutputFormat::Dot => dot::render_diff(&previous, &current, &mut std::io::stdout().lock())?,
+        _ => bail!("output format is not supported by 'graph --diff' (supported: plain, dot)"),
     }
 
-    None
+    Ok(())
+}
+
+/// Find the node of the service the given dependency `pattern` points into. In case of nested
+/// services the innermost one is chosen.
+fn find_dependent_service<'a>(pattern: &DepPattern, nodes: &'a [Node]) -> Option<&'a Node> {
+    nodes
+        .iter()
+        .filter(|node| pattern.is_child_of(&node.service.path.canonicalized))
+        .max_by_key(|node| node.service.path.canonicalized.len())
 }
 
 #[derive(Hash, PartialEq, Eq)]
@@ -60,13 +577,33 @@ struct ServiceIdent {
     name: String,
 }
 
-fn unique_service_names(services: Vec<Service>) -> HashMap<ServiceIdent, Service> {
+fn unique_service_names(services: Vec<Service>) -> Vec<(ServiceIdent, Service)> {
+    unique_names(&services)
+        .into_iter()
+        .zip(services)
+        .enumerate()
+        .map(|(idx, (name, svc))| {
+            (
+                ServiceIdent {
+                    id: format!("service_{}", idx + 1),
+                    name,
+                },
+                svc,
+            )
+        })
+        .collect()
+}
+
+/// Short but unique names of the given services: the name of the service directory, prefixed
+/// with as many parent directories (up to three) as needed to make all names unique (e.g.
+/// `libs.auth` and `services.auth`).
+pub(crate) fn unique_names(services: &[Service]) -> Vec<String> {
     let num_services = services.len();
 
     let mut ancestors = 0;
 
     for ancestor_count in [0usize, 1, 2, 3] {
-        let service_names = get_service_names(&services, ancestor_count);
+        let service_names = get_service_names(services, ancestor_count);
         if service_names.len() == num_services {
             ancestors = ancestor_count;
             break;
@@ -74,17 +611,8 @@ fn unique_service_names(services: Vec<Service>) -> HashMap<ServiceIdent, Service
     }
 
     services
-        .into_iter()
-        .enumerate()
-        .map(|(idx, svc)| {
-            (
-                ServiceIdent {
-                    id: format!("service_{}", idx + 1),
-                    name: service_identifier(&svc, ancestors),
-                },
-                svc,
-            )
-        })
+        .iter()
+        .map(|svc| service_identifier(svc, ancestors))
         .collect()
 }
 
@@ -106,3 +634,328 @@ fn service_identifier(service: &Service, num_ancestors: usize) -> String {
     parts.reverse();
     parts.join(".")
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::cli::{Direction, GraphOptions, Opts, OutputFormat};
+    use crate::config::{Config, DepPattern};
+    use crate::dependency;
+    use crate::lock::EdgeKind;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{Graph, render};
+
+    fn graph() -> Result<(TempDir, Graph)> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "libs/auth/Depsfile", "")?;
+        create_file(
+            dir.path(),
+            "services/auth/Depsfile",
+            "dependencies:\n  - ../../libs/auth\n  - ../../libs/auth/keys\n  - /Dockerfile\n",
+        )?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let graph = Graph::new(Service::discover(&opts)?);
+
+        Ok((dir, graph))
+    }
+
+    fn rendered(graph: &Graph, format: OutputFormat) -> Result<String> {
+        let opts = Opts::new(".", Config::default())?;
+        rendered_with(graph, format, opts, &GraphOptions::default())
+    }
+
+    fn rendered_with(
+        graph: &Graph,
+        format: OutputFormat,
+        mut opts: Opts,
+        options: &GraphOptions,
+    ) -> Result<String> {
+        opts.output = format;
+        opts.relative = true;
+
+        let mut output = Vec::new();
+        render(graph, &opts, options, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn graph_model() -> Result<()> {
+        let (_dir, graph) = graph()?;
+
+        // both services are named 'auth' so we need the parent directory as well
+        let names: Vec<_> = graph.nodes.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(vec!["libs.auth", "services.auth"], names);
+
+        // the Dockerfile is no service
+        assert_eq!(2, graph.edges.len());
+        assert!(
+            graph
+                .edges
+                .iter()
+                .all(|edge| edge.kind == EdgeKind::Explicit)
+        );
+
+        let edges = graph.service_edges();
+        assert_eq!(1, edges.len());
+        assert_eq!("service_2", edges[0].from);
+        assert_eq!("service_1", edges[0].to);
+
+        // ... but it is collected as pattern node
+        assert_eq!(1, graph.patterns.len());
+        assert_eq!("pattern_1", graph.patterns[0].id);
+        assert_eq!(1, graph.pattern_edges.len());
+        assert_eq!("service_2", graph.pattern_edges[0].from);
+        assert_eq!("pattern_1", graph.pattern_edges[0].to);
+
+        Ok(())
+    }
+
+    /// Chain of services: `web` -> `api` -> `lib`
+    fn chain() -> Result<(TempDir, Opts, Graph)> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "dependencies:\n  - ../api\n")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let graph = Graph::new(Service::discover(&opts)?);
+
+        Ok((dir, opts, graph))
+    }
+
+    fn names(graph: &Graph) -> Vec<&str> {
+        graph.nodes.iter().map(|node| node.name.as_str()).collect()
+    }
+
+    #[test]
+    fn graph_focus() -> Result<()> {
+        let (_dir, opts, graph) = chain()?;
+
+        let id = graph.find("api", &opts).map(|node| node.id.clone());
+        assert_eq!(Some("service_1".to_string()), id);
+        assert!(graph.find("./web/", &opts).is_some());
+        assert!(graph.find("unknown", &opts).is_none());
+
+        let down = graph.focus("service_3", None, Direction::Down);
+        assert_eq!(vec!["api", "lib", "web"], names(&down));
+        assert_eq!(2, down.edges.len());
+
+        let (_dir, _opts, graph) = chain()?;
+        let up = graph.focus("service_3", Some(1), Direction::Up);
+        assert_eq!(vec!["web"], names(&up));
+        assert!(up.edges.is_empty());
+
+        let (_dir, _opts, graph) = chain()?;
+        let both = graph.focus("service_1", Some(1), Direction::Both);
+        assert_eq!(vec!["api", "lib", "web"], names(&both));
+
+        let (_dir, _opts, graph) = chain()?;
+        let up = graph.focus("service_2", Some(1), Direction::Up);
+        assert_eq!(vec!["api", "lib"], names(&up));
+        assert_eq!(1, up.edges.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn graph_affected() -> Result<()> {
+        let (_dir, opts, mut graph) = chain()?;
+        let services = Service::discover(&opts)?;
+        let affected = dependency::resolve(services, vec!["api/main.go".to_string()], &opts)?;
+        graph.mark_affected(affected, &opts);
+
+        let affected: Vec<_> = graph
+            .nodes
+            .iter()
+            .filter(|node| node.is_affected())
+            .map(|node| node.name.as_str())
+            .collect();
+        assert_eq!(vec!["api", "web"], affected);
+
+        // web -> api is the only edge the trigger propagated along
+        let propagated: Vec<_> = graph
+            .edges
+            .iter()
+            .filter(|edge| graph.propagated(edge))
+            .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+            .collect();
+        assert_eq!(vec![("service_3", "service_1")], propagated);
+
+        let output = rendered_with(&graph, OutputFormat::Dot, opts, &GraphOptions::default())?;
+        assert!(output.contains("  service_3 -> service_1 [color=\"red\" penwidth=\"2\"];"));
+        assert!(output.contains("  service_1 -> service_2;"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn graph_propagated_by_trigger_source() -> Result<()> {
+        let (dir, opts, _graph) = chain()?;
+        create_file(
+            dir.path(),
+            "web/Depsfile",
+            "dependencies:\n  - ../api\n  - ../lib\n",
+        )?;
+
+        let services = Service::discover(&opts)?;
+        let mut graph = Graph::new(services.clone());
+        let changed = vec!["lib/lib.go".to_string(), "api/main.go".to_string()];
+        graph.mark_affected(dependency::resolve(services, changed, &opts)?, &opts);
+
+        // all services are affected, but 'web' was triggered by the change of 'api' only
+        assert!(graph.nodes.iter().all(|node| node.is_affected()));
+
+        let propagated: Vec<_> = graph
+            .edges
+            .iter()
+            .filter(|edge| graph.propagated(edge))
+            .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+            .collect();
+        assert_eq!(vec![("service_3", "service_1")], propagated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_dot() -> Result<()> {
+        let (dir, graph) = graph()?;
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let output = rendered_with(&graph, OutputFormat::Dot, opts, &GraphOptions::default())?;
+
+        assert!(output.contains("digraph dependencies {"));
+        assert!(output.contains("  subgraph cluster_1 {\n    label=\"libs\";\n"));
+        assert!(output.contains("  subgraph cluster_2 {\n    label=\"services\";\n"));
+        assert!(output.contains("    service_1 [label=\"libs.auth\"];"));
+        assert!(output.contains("  service_2 -> service_1;"));
+
+        // pattern nodes are not rendered by default
+        assert!(!output.contains("pattern_1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_dot_pattern_nodes() -> Result<()> {
+        let (dir, graph) = graph()?;
+        let mut config = Config::default();
+        config
+            .global_dependencies
+            .push(DepPattern::new("global.yaml", dir.path())?);
+
+        let opts = Opts::new(dir.path(), config)?;
+        let options = GraphOptions {
+            pattern_nodes: true,
+            global_nodes: true,
+            ..Default::default()
+        };
+        let output = rendered_with(&graph, OutputFormat::Dot, opts, &options)?;
+
+        assert!(output.contains("  pattern_1 [label=\"Dockerfile\" shape=\"note\"];"));
+        assert!(output.contains("  service_2 -> pattern_1;"));
+        assert!(output.contains("  subgraph cluster_global {"));
+        assert!(output.contains("    global_1 [label=\"global.yaml\" shape=\"octagon\"];"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_mermaid() -> Result<()> {
+        let (_dir, graph) = graph()?;
+        let output = rendered(&graph, OutputFormat::Mermaid)?;
+
+        assert!(output.contains("flowchart LR\n"));
+        assert!(output.contains("  service_2[\"services.auth\"]\n"));
+        assert!(output.contains("  service_2 --> service_1\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_plantuml() -> Result<()> {
+        let (_dir, graph) = graph()?;
+        let output = rendered(&graph, OutputFormat::PlantUml)?;
+
+        assert!(output.starts_with("@startuml\n"));
+        assert!(output.contains("component \"libs.auth\" as service_1\n"));
+        assert!(output.contains("service_2 --> service_1\n"));
+        assert!(output.ends_with("@enduml\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_json() -> Result<()> {
+        let (dir, graph) = graph()?;
+        let output = rendered(&graph, OutputFormat::Json)?;
+        let json: serde_json::Value = serde_json::from_str(&output)?;
+
+        assert_eq!(2, json["nodes"].as_array().map(|n| n.len()).unwrap_or(0));
+        assert_eq!("service_1", json["nodes"][0]["id"]);
+        assert_eq!("libs.auth", json["nodes"][0]["name"]);
+        assert!(json["nodes"][0]["languages"].is_array());
+
+        let edges = json["edges"].as_array().cloned().unwrap_or_default();
+        assert_eq!(2, edges.len());
+        assert_eq!("service_2", edges[0]["from"]);
+        assert_eq!("service_1", edges[0]["to"]);
+        assert_eq!("explicit", edges[0]["kind"]);
+        assert!(edges[0]["language"].is_null());
+        assert_eq!(
+            format!("{}/libs/auth", dir.path().canonicalize()?.display()),
+            edges[0]["pattern"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_graphml() -> Result<()> {
+        let (_dir, graph) = graph()?;
+        let output = rendered(&graph, OutputFormat::GraphMl)?;
+
+        assert!(output.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(output.contains("<graph id=\"dependencies\" edgedefault=\"directed\">"));
+        assert!(output.contains("<node id=\"service_1\">"));
+        assert!(output.contains("<data key=\"name\">libs.auth</data>"));
+        assert!(output.contains("<edge id=\"edge_1\" source=\"service_2\" target=\"service_1\">"));
+        assert!(output.contains("<data key=\"kind\">explicit</data>"));
+        assert!(output.ends_with("</graphml>\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_escaped_names() -> Result<()> {
+        let (dir, mut graph) = graph()?;
+        graph.nodes[0].name = "a\"b`<c>&d".to_string();
+
+        let output = rendered(&graph, OutputFormat::Mermaid)?;
+        assert!(output.contains("  service_1[\"a#quot;b#96;#lt;c#gt;#amp;d\"]\n"));
+
+        let output = rendered(&graph, OutputFormat::PlantUml)?;
+        assert!(output.contains("component \"a&#34;b`&#60;c&#62;&#38;d\" as service_1\n"));
+
+        let output = rendered(&graph, OutputFormat::GraphMl)?;
+        assert!(output.contains("<data key=\"name\">a&quot;b`&lt;c&gt;&amp;d</data>"));
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let output = rendered_with(&graph, OutputFormat::Dot, opts, &GraphOptions::default())?;
+        assert!(output.contains("    service_1 [label=\"a\\\"b`<c>&d\"];"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_unsupported() -> Result<()> {
+        let (_dir, graph) = graph()?;
+
+        assert!(rendered(&graph, OutputFormat::Yaml).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Result, Write};
+use std::path::Path;
+
+use crate::cli::{GraphOptions, Opts};
+use crate::config::Language;
+use crate::lock::{EdgeKind, Lockfile};
+
+use super::{Edge, Graph, Node, pattern_label};
+
+pub(super) const HEADER: &str =
+    "// Auto-generated by monodeps [https://github.com/kongo2002/monodeps]";
+
+/// Attributes of affected services and the edges the build trigger propagated along.
+const HIGHLIGHT: &str = "color=\"red\" penwidth=\"2\"";
+
+/// Render the graph in Graphviz dot notation.
+///
+/// Services are grouped into clusters by their parent directory and colored by their (first)
+/// language. Auto-discovered dependencies are rendered as dashed edges. Depending on the given
+/// `options`, dependencies outside of any service and global dependencies are rendered as
+/// separate nodes as well. Affected services and the edges the build trigger propagated along
+/// are highlighted in red.
+pub(super) fn render<W>(graph: &Graph, opts: &Opts, options: &GraphOptions, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    writeln!(w, "{HEADER}")?;
+    writeln!(w)?;
+    writeln!(w, "digraph dependencies {{")?;
+
+    let mut clusters: Vec<(String, Vec<&Node>)> = Vec::new();
+    for node in &graph.nodes {
+        let dir = directory(node, opts);
+        match clusters.iter_mut().find(|(cluster, _)| *cluster == dir) {
+            Some((_, nodes)) => nodes.push(node),
+            None => clusters.push((dir, vec![node])),
+        }
+    }
+
+    // nodes have to be declared before any edge as graphviz places nodes into the subgraph they
+    // are mentioned in first
+    let mut cluster_idx = 0;
+    for (dir, nodes) in &clusters {
+        if dir.is_empty() {
+            for node in nodes {
+                writeln!(w, "  {}", service_node(node))?;
+            }
+            continue;
+        }
+
+        cluster_idx += 1;
+        writeln!(w, "  subgraph cluster_{cluster_idx} {{")?;
+        writeln!(w, "    label=\"{}\";", escape(dir))?;
+        for node in nodes {
+            writeln!(w, "    {}", service_node(node))?;
+        }
+        writeln!(w, "  }}")?;
+    }
+
+    if options.pattern_nodes {
+        for pattern in &graph.patterns {
+            writeln!(
+                w,
+                "  {} [label=\"{}\" shape=\"note\"];",
+                pattern.id,
+                escape(&pattern_label(&pattern.pattern, opts))
+            )?;
+        }
+    }
+
+    if options.global_nodes && !opts.config.global_dependencies.is_empty() {
+        writeln!(w, "  subgraph cluster_global {{")?;
+        writeln!(w, "    label=\"global dependencies\";")?;
+        for (idx, pattern) in opts.config.global_dependencies.iter().enumerate() {
+            writeln!(
+                w,
+                "    global_{} [label=\"{}\" shape=\"octagon\"];",
+                idx + 1,
+                escape(&pattern_label(pattern, opts))
+            )?;
+        }
+        writeln!(w, "  }}")?;
+    }
+
+    writeln!(w)?;
+
+    for edge in graph.service_edges() {
+        writeln!(w, "  {}", edge_line(graph, edge))?;
+    }
+
+    if options.pattern_nodes {
+        for edge in graph.distinct_pattern_edges() {
+            writeln!(w, "  {}", edge_line(graph, edge))?;
+        }
+    }
+
+    writeln!(w, "}}")?;
+
+    Ok(())
+}
+
+fn service_node(node: &Node) -> String {
+    let mut attributes = vec![format!("label=\"{}\"", escape(&node.name))];
+
+    if let Some(language) = node.languages().first() {
+        attributes.push("style=\"filled\"".to_owned());
+        attributes.push(format!("fillcolor=\"{}\"", color(language)));
+    }
+    if node.is_affected() {
+        attributes.push(HIGHLIGHT.to_owned());
+    }
+
+    format!("{} [{}];", node.id, attributes.join(" "))
+}
+
+fn edge_line(graph: &Graph, edge: &Edge) -> String {
+    let mut attributes = Vec::new();
+
+    if edge.kind == EdgeKind::Auto {
+        attributes.push("style=\"dashed\"");
+    }
+    if graph.propagated(edge) {
+        attributes.push(HIGHLIGHT);
+    }
+
+    if attributes.is_empty() {
+        format!("{} -> {};", edge.from, edge.to)
+    } else {
+        format!("{} -> {} [{}];", edge.from, edge.to, attributes.join(" "))
+    }
+}
+
+/// Fill color of service nodes of the given language.
+fn color(language: &Language) -> &'static str {
+    match language {
+        Language::Golang => "#9fe3f5",
+        Language::Dotnet => "#d7bde2",
+        Language::Flutter => "#aed6f1",
+        Language::Kustomize => "#abebc6",
+        Language::JavaScript => "#f9e79f",
+        Language::Protobuf => "#f5cba7",
+        Language::Justfile => "#e5e8e8",
+        Language::Makefile => "#d5dbdb",
+        Language::Custom(_) => "#fadbd8",
+    }
+}
+
+/// Parent directory of the service relative to the target directory. Services directly in the
+/// target directory (or the target directory itself) have an empty directory.
+fn directory(node: &Node, opts: &Opts) -> String {
+    Path::new(&node.service.path.canonicalized)
+        .strip_prefix(&opts.target.canonicalized)
+        .ok()
+        .and_then(|relative| relative.parent())
+        .and_then(|parent| parent.to_str())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Escape the given value to be used in a quoted dot string.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Change of a node or edge in a graph diff.
+enum Change {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+impl Change {
+    fn of<T>(item: &T, previous: &BTreeSet<T>, current: &BTreeSet<T>) -> Self
+    where
+        T: Ord,
+    {
+        match (previous.contains(item), current.contains(item)) {
+            (false, true) => Change::Added,
+            (true, false) => Change::Removed,
+            _ => Change::Unchanged,
+        }
+    }
+
+    fn attributes(&self) -> &'static str {
+        match self {
+            Change::Added => " color=\"green\" fontcolor=\"green\"",
+            Change::Removed => " color=\"red\" fontcolor=\"red\" style=\"dashed\"",
+            Change::Unchanged => "",
+        }
+    }
+}
+
+/// Render the union of the `previous` and `current` graphs, with added nodes/edges colored in
+/// green and removed ones in red.
+pub(super) fn render_diff<W>(previous: &Lockfile, current: &Lockfile, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let previous_nodes: BTreeSet<_> = previous.services.keys().collect();
+    let current_nodes: BTreeSet<_> = current.services.keys().collect();
+    let previous_edges = lock_edges(previous);
+    let current_edges = lock_edges(current);
+
+    let ids: BTreeMap<_, _> = previous_nodes
+        .union(&current_nodes)
+        .enumerate()
+        .map(|(idx, name)| (*name, format!("service_{}", idx + 1)))
+        .collect();
+
+    writeln!(w, "{HEADER}")?;
+    writeln!(w)?;
+    writeln!(w, "digraph dependencies {{")?;
+
+    for (name, id) in &ids {
+        let change = Change::of(name, &previous_nodes, &current_nodes);
+        writeln!(w, "  {id} [label=\"{name}\"{}];", change.attributes())?;
+    }
+
+    writeln!(w)?;
+
+    for edge in previous_edges.union(&current_edges) {
+        let change = Change::of(edge, &previous_edges, &current_edges);
+        if let (Some(from), Some(to)) = (ids.get(edge.0), ids.get(edge.1)) {
+            match change {
+                Change::Unchanged => writeln!(w, "  {from} -> {to};")?,
+                _ => writeln!(
+                    w,
+                    "  {from} -> {to} [{}];",
+                    change.attributes().trim_start()
+                )?,
+            }
+        }
+    }
+
+    writeln!(w, "}}")?;
+
+    Ok(())
+}
+
+/// All edges between services of the given lockfile, regardless of their kind.
+fn lock_edges(lockfile: &Lockfile) -> BTreeSet<(&String, &String)> {
+    lockfile
+        .services
+        .iter()
+        .flat_map(|(name, svc)| svc.dependencies.iter().map(move |dep| (name, &dep.service)))
+        .collect()
+}
@@ -0,0 +1,83 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::cli::Opts;
+use crate::output::service_loc;
+use crate::utils::escape_html;
+
+use super::Graph;
+
+/// Render the graph in the GraphML format that is supported by tools like Gephi or yEd.
+pub(super) fn render<W>(graph: &Graph, opts: &Opts, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        w,
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+
+    for (key, target) in [
+        ("name", "node"),
+        ("path", "node"),
+        ("languages", "node"),
+        ("trigger", "node"),
+        ("kind", "edge"),
+        ("language", "edge"),
+        ("pattern", "edge"),
+        ("propagated", "edge"),
+    ] {
+        writeln!(
+            w,
+            "  <key id=\"{key}\" for=\"{target}\" attr.name=\"{key}\" attr.type=\"string\"/>"
+        )?;
+    }
+
+    writeln!(w, "  <graph id=\"dependencies\" edgedefault=\"directed\">")?;
+
+    for node in &graph.nodes {
+        let languages: Vec<_> = node.languages().iter().map(|l| l.to_string()).collect();
+
+        writeln!(w, "    <node id=\"{}\">", escape_html(&node.id))?;
+        data(w, "name", &node.name)?;
+        data(w, "path", &service_loc(&node.service, opts))?;
+        data(w, "languages", &languages.join(","))?;
+        if let Some(trigger) = &node.service.trigger {
+            data(w, "trigger", &trigger.to_string())?;
+        }
+        writeln!(w, "    </node>")?;
+    }
+
+    for (idx, edge) in graph.edges.iter().enumerate() {
+        writeln!(
+            w,
+            "    <edge id=\"edge_{}\" source=\"{}\" target=\"{}\">",
+            idx + 1,
+            escape_html(&edge.from),
+            escape_html(&edge.to)
+        )?;
+        data(w, "kind", &edge.kind.to_string())?;
+        if let Some(language) = &edge.language {
+            data(w, "language", &language.to_string())?;
+        }
+        data(w, "pattern", &edge.pattern.to_string())?;
+        if graph.propagated(edge) {
+            data(w, "propagated", "true")?;
+        }
+        writeln!(w, "    </edge>")?;
+    }
+
+    writeln!(w, "  </graph>")?;
+    writeln!(w, "</graphml>")?;
+
+    Ok(())
+}
+
+fn data<W>(w: &mut W, key: &str, value: &str) -> std::io::Result<()>
+where
+    W: Write,
+{
+    writeln!(w, "      <data key=\"{key}\">{}</data>", escape_html(value))
+}
@@ -0,0 +1,79 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cli::Opts;
+use crate::config::Language;
+use crate::lock::EdgeKind;
+use crate::output::service_loc;
+
+use super::Graph;
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    id: &'a str,
+    name: &'a str,
+    path: Cow<'a, str>,
+    languages: Vec<&'a Language>,
+    /// Build trigger of affected services (`graph --affected` only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trigger: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    kind: &'a EdgeKind,
+    language: Option<&'a Language>,
+    pattern: String,
+    /// Whether the build trigger propagated along the edge (`graph --affected` only).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    propagated: bool,
+}
+
+/// Render the graph as JSON document, including every edge together with the dependency pattern
+/// it originates from.
+pub(super) fn render<W>(graph: &Graph, opts: &Opts, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let json = JsonGraph {
+        nodes: graph
+            .nodes
+            .iter()
+            .map(|node| JsonNode {
+                id: &node.id,
+                name: &node.name,
+                path: service_loc(&node.service, opts),
+                languages: node.languages(),
+                trigger: node.service.trigger.as_ref().map(|t| t.to_string()),
+            })
+            .collect(),
+        edges: graph
+            .edges
+            .iter()
+            .map(|edge| JsonEdge {
+                from: &edge.from,
+                to: &edge.to,
+                kind: &edge.kind,
+                language: edge.language.as_ref(),
+                pattern: edge.pattern.to_string(),
+                propagated: graph.propagated(edge),
+            })
+            .collect(),
+    };
+
+    serde_json::to_writer(&mut *w, &json)?;
+    writeln!(w)?;
+
+    Ok(())
+}
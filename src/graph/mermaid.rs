@@ -0,0 +1,64 @@
+use std::io::{Result, Write};
+
+use super::Graph;
+
+/// Render the graph as Mermaid flowchart that can be embedded into Markdown documents.
+pub(super) fn render<W>(graph: &Graph, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    writeln!(
+        w,
+        "%% Auto-generated by monodeps [https://github.com/kongo2002/monodeps]"
+    )?;
+    writeln!(w, "flowchart LR")?;
+
+    for node in &graph.nodes {
+        writeln!(w, "  {}[\"{}\"]", node.id, escape(&node.name))?;
+    }
+
+    let edges = graph.service_edges();
+    for edge in &edges {
+        writeln!(w, "  {} --> {}", edge.from, edge.to)?;
+    }
+
+    // highlight affected services and the edges the build trigger propagated along
+    let affected: Vec<_> = graph
+        .nodes
+        .iter()
+        .filter(|node| node.is_affected())
+        .map(|node| node.id.as_str())
+        .collect();
+    if !affected.is_empty() {
+        writeln!(w, "  classDef affected stroke:#d00,stroke-width:3px")?;
+        writeln!(w, "  class {} affected", affected.join(","))?;
+    }
+
+    let propagated: Vec<_> = edges
+        .iter()
+        .enumerate()
+        .filter(|(_, edge)| graph.propagated(edge))
+        .map(|(idx, _)| idx.to_string())
+        .collect();
+    if !propagated.is_empty() {
+        writeln!(
+            w,
+            "  linkStyle {} stroke:#d00,stroke-width:3px",
+            propagated.join(",")
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Escape the given value to be used in a quoted Mermaid label: these may contain HTML and
+/// markdown, so all special characters are replaced with Mermaid's entity codes (`#quot;`).
+fn escape(value: &str) -> String {
+    value
+        .replace('#', "#35;")
+        .replace('&', "#amp;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('"', "#quot;")
+        .replace('`', "#96;")
+}
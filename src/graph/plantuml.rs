@@ -0,0 +1,56 @@
+use std::io::{Result, Write};
+
+use super::Graph;
+
+/// Render the graph as PlantUML component diagram.
+pub(super) fn render<W>(graph: &Graph, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    writeln!(w, "@startuml")?;
+    writeln!(
+        w,
+        "' Auto-generated by monodeps [https://github.com/kongo2002/monodeps]"
+    )?;
+    writeln!(w)?;
+
+    // affected services and the edges the build trigger propagated along are highlighted
+    for node in &graph.nodes {
+        if node.is_affected() {
+            writeln!(
+                w,
+                "component \"{}\" as {} #ffcccc",
+                escape(&node.name),
+                node.id
+            )?;
+        } else {
+            writeln!(w, "component \"{}\" as {}", escape(&node.name), node.id)?;
+        }
+    }
+
+    writeln!(w)?;
+
+    for edge in graph.service_edges() {
+        if graph.propagated(edge) {
+            writeln!(w, "{} -[#red]-> {}", edge.from, edge.to)?;
+        } else {
+            writeln!(w, "{} --> {}", edge.from, edge.to)?;
+        }
+    }
+
+    writeln!(w, "@enduml")?;
+
+    Ok(())
+}
+
+/// Escape the given value to be used in a quoted PlantUML name: quotes cannot be escaped there and
+/// the name is rendered as creole (including HTML tags), so these characters are replaced with
+/// numeric character references.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&#38;")
+        .replace('<', "&#60;")
+        .replace('>', "&#62;")
+        .replace('"', "&#34;")
+        .replace('\\', "&#92;")
+}
@@ -0,0 +1,77 @@
+//! monodeps is a library (and CLI) to help with change detection in mono-repository setups in
+//! order to determine which services or folders are candidate for build and publish in CI/CD
+//! environments.
+//!
+//! The main entrypoints are:
+//!
+//! - `Opts`: options controlling discovery and resolution, constructed programmatically
+//!   (`Opts::new`) or for another target directory (`Opts::for_target`)
+//! - `discover`: discover all services (and their dependencies) in the target directory
+//! - `affected`: resolve a list of changed files into the list of affected services, each of
+//!   them annotated with the `BuildTrigger` that caused it to be affected
+//! - `LanguageAnalyzer`: implement this trait in order to auto-discover dependencies of custom
+//!   languages/frameworks and register it via `Opts::register_analyzer`
+//!
+//! ```no_run
+//! use monodeps::{Config, Opts};
+//!
+//! let opts = Opts::new("./my-repository", Config::default())?;
+//! let changed_files = vec!["services/auth/main.go".to_string()];
+//!
+//! for service in monodeps::affected(changed_files, &opts)? {
+//!     println!("{} [{:?}]", service.path.canonicalized, service.trigger);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::Result;
+
+mod app;
+mod cache;
+mod cli;
+mod closure;
+mod config;
+mod dependency;
+mod fingerprint;
+mod git;
+mod graph;
+mod lock;
+mod manifest;
+mod output;
+mod path;
+mod pipeline;
+mod report;
+mod run;
+mod service;
+mod shard;
+mod state;
+mod template;
+#[cfg(test)]
+mod test_util;
+mod utils;
+mod watch;
+
+#[doc(hidden)]
+pub use self::app::main;
+pub use self::cli::Opts;
+pub use self::config::{
+    AutoDiscoveryConfig, Config, DepPattern, Depsfile, DepsfileType, DotnetConfig, GoDepsConfig,
+    Language, PluginConfig, ReferenceBase, ReferenceConfig,
+};
+pub use self::path::PathInfo;
+pub use self::service::{AutoDependency, BuildTrigger, LanguageAnalyzer, Service};
+
+/// Discover all services in the target directory of the given `Opts`, including all explicit
+/// and auto-discovered dependencies.
+pub fn discover(opts: &Opts) -> Result<Vec<Service>> {
+    Service::discover(opts)
+}
+
+/// Discover all services in the target directory of the given `Opts` and determine all services
+/// that are affected by the given list of `changed_files`. The changed files are expected to be
+/// relative to the target directory.
+pub fn affected(changed_files: Vec<String>, opts: &Opts) -> Result<Vec<Service>> {
+    let services = Service::discover(opts)?;
+
+    dependency::resolve(services, changed_files, opts)
+}
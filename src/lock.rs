@@ -0,0 +1,521 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Opts;
+use crate::config::DepPattern;
+use crate::git::Worktree;
+use crate::path::PathInfo;
+use crate::service::Service;
+
+/// File name of the lockfile that is written into the target directory.
+pub const LOCKFILE: &str = "monodeps.lock";
+
+/// The version of the lockfile format.
+const LOCKFILE_VERSION: u32 = 1;
+
+/// The `Lockfile` is a deterministic snapshot of the discovered dependency graph, meaning all
+/// services, their languages, all explicit and auto-discovered edges between them and all
+/// dependencies on files outside of any service. All paths are relative to the target directory,
+/// so the lockfile can be committed into the repository.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub version: u32,
+    pub services: BTreeMap<String, LockedService>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LockedService {
+    pub languages: Vec<String>,
+    pub dependencies: BTreeSet<LockedEdge>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub files: BTreeSet<LockedPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    Explicit,
+    Auto,
+}
+
+/// A dependency edge to another service (identified by its relative path).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LockedEdge {
+    pub service: String,
+    pub kind: EdgeKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// A dependency on files that do not belong to any service, either a path (relative to the
+/// target directory) or a regular expression matching the canonicalized file paths.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockedPattern {
+    Path(String),
+    Regex(String),
+}
+
+/// A single difference between two lockfiles.
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    ServiceAdded(String),
+    ServiceRemoved(String),
+    LanguagesChanged(String, Vec<String>, Vec<String>),
+    EdgeAdded(String, LockedEdge),
+    EdgeRemoved(String, LockedEdge),
+    FileAdded(String, LockedPattern),
+    FileRemoved(String, LockedPattern),
+}
+
+impl Lockfile {
+    /// Build the lockfile of the given list of discovered services.
+    pub fn new(services: &[Service], opts: &Opts) -> Self {
+        let services = services
+            .iter()
+            .map(|svc| {
+                let mut languages: Vec<_> = svc
+                    .depsfile
+                    .languages
+                    .iter()
+                    .map(|lang| lang.to_string())
+                    .collect();
+                languages.sort();
+                languages.dedup();
+
+                let explicit = svc
+                    .depsfile
+                    .dependencies
+                    .iter()
+                    .map(|pattern| (pattern, EdgeKind::Explicit, None));
+                let auto = svc.auto_dependencies.iter().map(|auto| {
+                    (
+                        &auto.pattern,
+                        EdgeKind::Auto,
+                        Some(auto.language.to_string()),
+                    )
+                });
+
+                let mut dependencies = BTreeSet::new();
+                let mut files = BTreeSet::new();
+
+                for (pattern, kind, language) in explicit.chain(auto) {
+                    match dependent_service(pattern, services) {
+                        Some(dependency) if std::ptr::eq(dependency, svc) => {}
+                        Some(dependency) => {
+                            dependencies.insert(LockedEdge {
+                                service: relative_name(&dependency.path, opts),
+                                kind,
+                                language,
+                            });
+                        }
+                        None => {
+                            files.insert(LockedPattern::new(pattern, opts));
+                        }
+                    }
+                }
+
+                (
+                    relative_name(&svc.path, opts),
+                    LockedService {
+                        languages,
+                        dependencies,
+                        files,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            version: LOCKFILE_VERSION,
+            services,
+        }
+    }
+
+    /// Build the lockfile of the services discovered at the given git `revision`. The revision is
+    /// checked out into a temporary worktree and discovered with the very same options.
+    pub fn at_revision(revision: &str, opts: &Opts) -> Result<Self> {
+        let worktree = Worktree::checkout(&opts.target.canonicalized, revision)?;
+        let base_opts = opts.for_target(worktree.target())?;
+        let services = Service::discover(&base_opts)?;
+
+        Ok(Self::new(&services, &base_opts))
+    }
+
+    /// Load the lockfile from the given `path`.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read lockfile {}: {err}", path.display()))?;
+        let lockfile: Lockfile = serde_json::from_str(&content)
+            .map_err(|err| anyhow!("invalid lockfile {}: {err}", path.display()))?;
+
+        if lockfile.version != LOCKFILE_VERSION {
+            bail!(
+                "unsupported lockfile version {} (expected {LOCKFILE_VERSION})",
+                lockfile.version
+            );
+        }
+
+        Ok(lockfile)
+    }
+
+    /// Write the lockfile to the given `path`.
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+
+        std::fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// Determine all differences from this (previous) lockfile to the given `current` one.
+    pub fn diff(&self, current: &Lockfile) -> Vec<Drift> {
+        let mut drifts = Vec::new();
+        let empty = BTreeSet::new();
+        let no_files = BTreeSet::new();
+
+        let names: BTreeSet<_> = self
+            .services
+            .keys()
+            .chain(current.services.keys())
+            .collect();
+
+        for name in names {
+            let previous = self.services.get(name);
+            let next = current.services.get(name);
+
+            match (previous, next) {
+                (None, Some(_)) => drifts.push(Drift::ServiceAdded(name.clone())),
+                (Some(_), None) => drifts.push(Drift::ServiceRemoved(name.clone())),
+                (Some(previous), Some(next)) if previous.languages != next.languages => drifts
+                    .push(Drift::LanguagesChanged(
+                        name.clone(),
+                        previous.languages.clone(),
+                        next.languages.clone(),
+                    )),
+                _ => {}
+            }
+
+            let previous_edges = previous.map(|svc| &svc.dependencies).unwrap_or(&empty);
+            let next_edges = next.map(|svc| &svc.dependencies).unwrap_or(&empty);
+
+            drifts.extend(
+                next_edges
+                    .difference(previous_edges)
+                    .map(|edge| Drift::EdgeAdded(name.clone(), edge.clone())),
+            );
+            drifts.extend(
+                previous_edges
+                    .difference(next_edges)
+                    .map(|edge| Drift::EdgeRemoved(name.clone(), edge.clone())),
+            );
+
+            let previous_files = previous.map(|svc| &svc.files).unwrap_or(&no_files);
+            let next_files = next.map(|svc| &svc.files).unwrap_or(&no_files);
+
+            drifts.extend(
+                next_files
+                    .difference(previous_files)
+                    .map(|file| Drift::FileAdded(name.clone(), file.clone())),
+            );
+            drifts.extend(
+                previous_files
+                    .difference(next_files)
+                    .map(|file| Drift::FileRemoved(name.clone(), file.clone())),
+            );
+        }
+
+        drifts
+    }
+}
+
+impl LockedService {
+    /// All dependency patterns of the service (on other services as well as on files), resolved
+    /// against the given target directory of `opts`.
+    pub fn patterns(&self, opts: &Opts) -> Result<Vec<DepPattern>> {
+        let target = &opts.target.canonicalized;
+
+        self.dependencies
+            .iter()
+            .map(|edge| DepPattern::plain(&edge.service, target))
+            .chain(self.files.iter().map(|file| match file {
+                LockedPattern::Path(path) => DepPattern::plain(path, target),
+                LockedPattern::Regex(regex) => DepPattern::regex(regex, target),
+            }))
+            .collect()
+    }
+}
+
+impl LockedPattern {
+    fn new(pattern: &DepPattern, opts: &Opts) -> Self {
+        match pattern.hash() {
+            Some(path) => Self::Path(
+                Path::new(path)
+                    .strip_prefix(&opts.target.canonicalized)
+                    .ok()
+                    .and_then(|path| path.to_str())
+                    .unwrap_or(path)
+                    .to_owned(),
+            ),
+            None => Self::Regex(pattern.to_string()),
+        }
+    }
+}
+
+impl Display for EdgeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EdgeKind::Explicit => f.write_str("explicit"),
+            EdgeKind::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+impl Display for LockedEdge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.language {
+            Some(language) => f.write_fmt(format_args!(
+                "{} ({}: {})",
+                self.service, self.kind, language
+            )),
+            None => f.write_fmt(format_args!("{} ({})", self.service, self.kind)),
+        }
+    }
+}
+
+impl Display for LockedPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockedPattern::Path(path) => f.write_fmt(format_args!("file {path}")),
+            LockedPattern::Regex(regex) => f.write_fmt(format_args!("files /{regex}/")),
+        }
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::ServiceAdded(name) => f.write_fmt(format_args!("+ service {name}")),
+            Drift::ServiceRemoved(name) => f.write_fmt(format_args!("- service {name}")),
+            Drift::LanguagesChanged(name, previous, next) => f.write_fmt(format_args!(
+                "~ service {name} languages [{}] -> [{}]",
+                previous.join(", "),
+                next.join(", ")
+            )),
+            Drift::EdgeAdded(name, edge) => f.write_fmt(format_args!("+ {name} -> {edge}")),
+            Drift::EdgeRemoved(name, edge) => f.write_fmt(format_args!("- {name} -> {edge}")),
+            Drift::FileAdded(name, file) => f.write_fmt(format_args!("+ {name} -> {file}")),
+            Drift::FileRemoved(name, file) => f.write_fmt(format_args!("- {name} -> {file}")),
+        }
+    }
+}
+
+/// Location of the lockfile in the target directory.
+pub fn path(opts: &Opts) -> PathBuf {
+    Path::new(&opts.target.canonicalized).join(LOCKFILE)
+}
+
+/// Find the service the given dependency `pattern` points into. In case of nested services the
+/// innermost one is chosen.
+fn dependent_service<'a>(pattern: &DepPattern, services: &'a [Service]) -> Option<&'a Service> {
+    services
+        .iter()
+        .filter(|svc| pattern.is_child_of(&svc.path.canonicalized))
+        .max_by_key(|svc| svc.path.canonicalized.len())
+}
+
+/// The given service path relative to the target directory (without any `./` prefix), as it is
+/// used to identify services in the lockfile.
+pub(crate) fn relative_name(path: &PathInfo, opts: &Opts) -> String {
+    Path::new(&path.canonicalized)
+        .strip_prefix(&opts.target.canonicalized)
+        .ok()
+        .and_then(|path| path.to_str())
+        .map(|path| if path.is_empty() { "." } else { path })
+        .unwrap_or(&path.canonicalized)
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{Drift, EdgeKind, LockedEdge, LockedPattern, Lockfile};
+
+    fn repository() -> Result<TempDir> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "languages: [golang]\n")?;
+        create_file(
+            dir.path(),
+            "api/Depsfile",
+            "languages: [golang]\ndependencies:\n  - ../lib\n",
+        )?;
+        Ok(dir)
+    }
+
+    fn lockfile(dir: &TempDir) -> Result<Lockfile> {
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let services = Service::discover(&opts)?;
+        Ok(Lockfile::new(&services, &opts))
+    }
+
+    #[test]
+    fn lock_services() -> Result<()> {
+        let dir = repository()?;
+        let lock = lockfile(&dir)?;
+
+        assert_eq!(vec!["api", "lib"], lock.services.keys().collect::<Vec<_>>());
+
+        let api = &lock.services["api"];
+        assert_eq!(vec!["go".to_string()], api.languages);
+        assert_eq!(
+            vec![&LockedEdge {
+                service: "lib".to_string(),
+                kind: EdgeKind::Explicit,
+                language: None,
+            }],
+            api.dependencies.iter().collect::<Vec<_>>()
+        );
+        assert!(lock.services["lib"].dependencies.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_roundtrip() -> Result<()> {
+        let dir = repository()?;
+        let lock = lockfile(&dir)?;
+        let path = dir.path().join("monodeps.lock");
+
+        lock.write(&path)?;
+        let content = std::fs::read_to_string(&path)?;
+
+        // writing the same graph twice yields the identical file
+        lockfile(&dir)?.write(&path)?;
+        assert_eq!(content, std::fs::read_to_string(&path)?);
+
+        assert_eq!(lock, Lockfile::load(&path)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_no_drift() -> Result<()> {
+        let dir = repository()?;
+
+        assert!(lockfile(&dir)?.diff(&lockfile(&dir)?).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_drift() -> Result<()> {
+        let dir = repository()?;
+        let previous = lockfile(&dir)?;
+
+        create_file(dir.path(), "api/Depsfile", "languages: [golang]\n")?;
+        create_file(
+            dir.path(),
+            "web/Depsfile",
+            "dependencies:\n  - ../api\n  - ../api/static\n",
+        )?;
+
+        let drifts: Vec<_> = previous
+            .diff(&lockfile(&dir)?)
+            .iter()
+            .map(|drift| drift.to_string())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "- api -> lib (explicit)",
+                "+ service web",
+                "+ web -> api (explicit)",
+            ],
+            drifts
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_files() -> Result<()> {
+        let dir = repository()?;
+        create_file(
+            dir.path(),
+            "api/Depsfile",
+            "languages: [golang]\ndependencies:\n  - ../lib\n  - ../shared/config.yaml\n  - regex: \\.proto$\n",
+        )?;
+        let previous = lockfile(&dir)?;
+
+        assert_eq!(
+            vec![
+                &LockedPattern::Path("shared/config.yaml".to_string()),
+                &LockedPattern::Regex("\\.proto$".to_string()),
+            ],
+            previous.services["api"].files.iter().collect::<Vec<_>>()
+        );
+        assert!(previous.services["lib"].files.is_empty());
+
+        create_file(
+            dir.path(),
+            "api/Depsfile",
+            "languages: [golang]\ndependencies:\n  - ../lib\n",
+        )?;
+
+        let drifts: Vec<_> = previous
+            .diff(&lockfile(&dir)?)
+            .iter()
+            .map(|drift| drift.to_string())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "- api -> file shared/config.yaml",
+                "- api -> files /\\.proto$/",
+            ],
+            drifts
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_languages_changed() -> Result<()> {
+        let dir = repository()?;
+        let previous = lockfile(&dir)?;
+
+        create_file(dir.path(), "lib/Depsfile", "languages: [dotnet]\n")?;
+
+        let drifts = previous.diff(&lockfile(&dir)?);
+
+        assert_eq!(
+            vec![Drift::LanguagesChanged(
+                "lib".to_string(),
+                vec!["go".to_string()],
+                vec!["C#".to_string()]
+            )],
+            drifts
+        );
+
+        Ok(())
+    }
+}
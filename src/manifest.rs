@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Opts;
+use crate::fingerprint;
+use crate::lock::relative_name;
+use crate::service::{BuildTrigger, Service};
+
+/// Bump this version whenever the manifest format changes.
+const MANIFEST_VERSION: u32 = 1;
+
+/// The fingerprint manifest records the fingerprints (see `fingerprint::fingerprints`) of all
+/// services by their path relative to the target directory, e.g. of the last successful run.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub services: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    /// Create the manifest of the given (discovered) services.
+    pub fn new(services: &[Service], opts: &Opts) -> Result<Self> {
+        let fingerprints = fingerprint::fingerprints(services.to_vec(), opts)?;
+
+        let services = services
+            .iter()
+            .filter_map(|svc| {
+                fingerprints
+                    .get(&svc.path.canonicalized)
+                    .map(|fingerprint| (relative_name(&svc.path, opts), fingerprint.clone()))
+            })
+            .collect();
+
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            services,
+        })
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read manifest {}: {err}", path.display()))?;
+        let manifest: Manifest = serde_json::from_str(&content)
+            .map_err(|err| anyhow!("invalid manifest {}: {err}", path.display()))?;
+
+        if manifest.version != MANIFEST_VERSION {
+            bail!(
+                "unsupported manifest version {} (expected {MANIFEST_VERSION})",
+                manifest.version
+            );
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+
+        std::fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// Determine all of the given `services` whose fingerprint in this (current) manifest differs
+    /// from the one of the `previous` manifest, including services that are missing in the
+    /// `previous` manifest. These services are triggered by a `BuildTrigger::FingerprintChange`.
+    ///
+    /// Without any `previous` manifest (e.g. on the first run) all services are returned.
+    pub fn changed(
+        &self,
+        services: Vec<Service>,
+        previous: Option<&Manifest>,
+        opts: &Opts,
+    ) -> Vec<Service> {
+        services
+            .into_iter()
+            .filter_map(|mut svc| {
+                let name = relative_name(&svc.path, opts);
+                let current = self.services.get(&name);
+                let before = previous.and_then(|previous| previous.services.get(&name));
+
+                if current.is_some() && current == before {
+                    return None;
+                }
+
+                svc.trigger(BuildTrigger::FingerprintChange);
+                Some(svc)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::lock::relative_name;
+    use crate::service::{BuildTrigger, Service};
+    use crate::test_util::create_file;
+
+    use super::Manifest;
+
+    fn names(services: &[Service], opts: &Opts) -> Vec<String> {
+        let mut names: Vec<_> = services
+            .iter()
+            .map(|svc| relative_name(&svc.path, opts))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn changed_since_manifest() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "lib/lib.go", "package lib")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let services = Service::discover(&opts)?;
+        let previous = Manifest::new(&services, &opts)?;
+
+        // manifest round trip
+        let path = dir.path().join("manifest.json");
+        previous.write(&path)?;
+        let previous = Manifest::load(&path)?;
+        assert_eq!(
+            vec!["api", "lib", "web"],
+            previous.services.keys().collect::<Vec<_>>()
+        );
+
+        // nothing changed
+        let current = Manifest::new(&services, &opts)?;
+        assert!(
+            current
+                .changed(services.clone(), Some(&previous), &opts)
+                .is_empty()
+        );
+
+        // without any previous manifest everything changed
+        assert_eq!(3, current.changed(services, None, &opts).len());
+
+        create_file(dir.path(), "lib/lib.go", "package lib // changed")?;
+        create_file(dir.path(), "new/Depsfile", "")?;
+
+        let services = Service::discover(&opts)?;
+        let current = Manifest::new(&services, &opts)?;
+        let changed = current.changed(services, Some(&previous), &opts);
+
+        assert_eq!(vec!["api", "lib", "new"], names(&changed, &opts));
+        assert!(
+            changed
+                .iter()
+                .all(|svc| svc.trigger == Some(BuildTrigger::FingerprintChange))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_version() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        let path = dir.path().join("manifest.json");
+        std::fs::write(&path, "{\"version\": 99, \"services\": {}}")?;
+
+        assert!(Manifest::load(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use yaml_rust::{Yaml, YamlEmitter};
+
+use crate::cli::{Opts, OutputFormat};
+use crate::config::Language;
+use crate::fingerprint;
+use crate::graph;
+use crate::pipeline;
+use crate::service::Service;
+
+mod markdown;
+
+/// Delimiter of multiline values written to `$GITHUB_OUTPUT`.
+const GITHUB_DELIMITER: &str = "MONODEPS_EOF";
+
+/// GitHub Actions `strategy.matrix` with one job per service.
+#[derive(Serialize)]
+struct Matrix<'a> {
+    include: Vec<MatrixEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct MatrixEntry<'a> {
+    name: String,
+    path: Cow<'a, str>,
+    languages: Vec<&'a Language>,
+}
+
+/// Service with the changed files concerning it (see `--files`) and its fingerprint (see
+/// `--fingerprint`).
+#[derive(Serialize)]
+struct ServiceEntry<'a> {
+    path: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed_files: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dependency_files: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+}
+
+/// Output the determined list of services to STDOUT.
+///
+/// Depending on the specified `OutputFormat` the output will be formatted in either plaintext,
+/// JSON, YAML, as GitHub Actions matrix, as CI pipeline (see `pipeline::render`), as markdown
+/// summary including the `changed_files` or using a user-defined template (see `Template`).
+///
+/// The `discovered` services (i.e. all services of the target directory) are required for the
+/// fingerprints of the services with `--fingerprint`.
+pub fn output(
+    services: Vec<Service>,
+    discovered: &[Service],
+    changed_files: &[String],
+    opts: &Opts,
+) -> Result<()> {
+    match opts.output {
+        // graph formats are only supported by the 'graph' operation
+        OutputFormat::Plain
+        | OutputFormat::Dot
+        | OutputFormat::Mermaid
+        | OutputFormat::PlantUml
+        | OutputFormat::GraphMl => {
+            print_services(std::io::stdout(), services, opts);
+        }
+        OutputFormat::Json if opts.files || opts.fingerprint => {
+            let entries = service_entries(&services, discovered, opts)?;
+            _ = serde_json::to_writer(std::io::stdout(), &entries);
+        }
+        OutputFormat::Json => {
+            let to_output = services
+                .iter()
+                .map(|svc| service_loc(svc, opts))
+                .collect::<Vec<_>>();
+            _ = serde_json::to_writer(std::io::stdout(), &to_output);
+        }
+        OutputFormat::GitHubMatrix => {
+            _ = serde_json::to_writer(std::io::stdout(), &github_matrix(&services, opts));
+        }
+        OutputFormat::GitLab | OutputFormat::Buildkite => {
+            pipeline::render(services, opts, &mut std::io::stdout().lock())?;
+        }
+        OutputFormat::Markdown => {
+            markdown::render(
+                &services,
+                changed_files,
+                opts,
+                &mut std::io::stdout().lock(),
+            )?;
+        }
+        OutputFormat::Template => {
+            let template = opts.format_template.as_ref().ok_or_else(|| {
+                anyhow!("missing output template (see '--format-template' and '--template-file')")
+            })?;
+            template.render(&services, opts, &mut std::io::stdout().lock())?;
+        }
+        OutputFormat::Yaml => {
+            let mut output = String::new();
+            {
+                let mut emitter = YamlEmitter::new(&mut output);
+
+                let to_output = if opts.files || opts.fingerprint {
+                    service_entries(&services, discovered, opts)?
+                        .iter()
+                        .map(yaml_service_entry)
+                        .collect::<Vec<_>>()
+                } else {
+                    services
+                        .iter()
+                        .map(|svc| Yaml::String(service_loc(svc, opts).to_string()))
+                        .collect::<Vec<_>>()
+                };
+
+                let array = Yaml::Array(to_output);
+                _ = emitter.dump(&array);
+            }
+
+            // we want to omit the `---` on the first line
+            for line in output.lines().skip(1) {
+                println!("{}", line);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Entries of the given services including the details requested in the `Opts`, the fingerprints
+/// are determined based on all `discovered` services.
+fn service_entries<'a>(
+    services: &'a [Service],
+    discovered: &[Service],
+    opts: &Opts,
+) -> Result<Vec<ServiceEntry<'a>>> {
+    let mut fingerprints = if opts.fingerprint {
+        fingerprint::fingerprints(discovered.to_vec(), opts)?
+    } else {
+        HashMap::new()
+    };
+
+    Ok(services
+        .iter()
+        .map(|svc| ServiceEntry {
+            path: service_loc(svc, opts),
+            changed_files: opts.files.then_some(svc.changed_files.as_slice()),
+            dependency_files: opts.files.then_some(svc.dependency_files.as_slice()),
+            fingerprint: fingerprints.remove(&svc.path.canonicalized),
+        })
+        .collect())
+}
+
+fn yaml_service_entry(entry: &ServiceEntry) -> Yaml {
+    let files = |files: &[String]| Yaml::Array(files.iter().cloned().map(Yaml::String).collect());
+
+    let mut hash = yaml_rust::yaml::Hash::new();
+    hash.insert(
+        Yaml::String("path".to_owned()),
+        Yaml::String(entry.path.to_string()),
+    );
+    if let Some(changed_files) = entry.changed_files {
+        hash.insert(
+            Yaml::String("changed_files".to_owned()),
+            files(changed_files),
+        );
+    }
+    if let Some(dependency_files) = entry.dependency_files {
+        hash.insert(
+            Yaml::String("dependency_files".to_owned()),
+            files(dependency_files),
+        );
+    }
+    if let Some(fingerprint) = &entry.fingerprint {
+        hash.insert(
+            Yaml::String("fingerprint".to_owned()),
+            Yaml::String(fingerprint.clone()),
+        );
+    }
+    Yaml::Hash(hash)
+}
+
+/// Build the GitHub Actions matrix of the given services, sorted by path. Every entry contains the
+/// short but unique name of the service (see `graph::unique_names`), its path and languages.
+fn github_matrix<'a>(services: &'a [Service], opts: &Opts) -> Matrix<'a> {
+    let mut include: Vec<_> = graph::unique_names(services)
+        .into_iter()
+        .zip(services)
+        .map(|(name, svc)| MatrixEntry {
+            name,
+            path: service_loc(svc, opts),
+            languages: svc.languages(),
+        })
+        .collect();
+
+    include.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Matrix { include }
+}
+
+/// Append the given affected services to the file given in the `$GITHUB_OUTPUT` environment
+/// variable, so they can be used in subsequent steps and jobs of a GitHub Actions workflow.
+pub fn write_github_output(services: &[Service], opts: &Opts) -> Result<()> {
+    let path = std::env::var("GITHUB_OUTPUT").map_err(|_| {
+        anyhow!("'--github-output' requires the environment variable GITHUB_OUTPUT")
+    })?;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| anyhow!("failed to open GITHUB_OUTPUT file {path}: {err}"))?;
+
+    github_output(&mut file, services, opts)
+}
+
+/// Write the `matrix` (see `github_matrix`), `any_affected` and the (multiline) list of
+/// `services` entries in the `$GITHUB_OUTPUT` format.
+fn github_output<W>(w: &mut W, services: &[Service], opts: &Opts) -> Result<()>
+where
+    W: Write,
+{
+    let matrix = github_matrix(services, opts);
+
+    writeln!(w, "matrix={}", serde_json::to_string(&matrix)?)?;
+    writeln!(w, "any_affected={}", !services.is_empty())?;
+
+    writeln!(w, "services<<{GITHUB_DELIMITER}")?;
+    for entry in matrix.include {
+        writeln!(w, "{}", entry.path)?;
+    }
+    writeln!(w, "{GITHUB_DELIMITER}")?;
+
+    Ok(())
+}
+
+/// Depending on the specified `--relative` option, we output either the full (canonicalized) or
+/// relative path.
+pub fn service_loc<'a>(service: &'a Service, opts: &Opts) -> Cow<'a, str> {
+    if opts.relative {
+        Cow::from(service.path.relative_to(&opts.target))
+    } else {
+        Cow::from(&service.path.canonicalized)
+    }
+}
+
+/// Print the plaintext output of the given list of services.
+///
+/// If specified via the `--verbose` flag, the output will include the `BuildTrigger` (source) of
+/// the dependency.
+pub fn print_services<W>(mut w: W, services: Vec<Service>, opts: &Opts)
+where
+    W: std::io::Write,
+{
+    for svc in services {
+        if !opts.verbose {
+            _ = w.write_fmt(format_args!("{}\n", service_loc(&svc, opts)));
+        } else {
+            _ = w.write_fmt(format_args!(
+                "{} [{}]\n",
+                service_loc(&svc, opts),
+                svc.trigger
+                    .as_ref()
+                    .map(|t| t.to_string())
+                    .unwrap_or_default()
+            ));
+        }
+
+        if opts.files {
+            let mut files: Vec<_> = svc
+                .changed_files
+                .iter()
+                .chain(&svc.dependency_files)
+                .collect();
+            files.sort();
+            files.dedup();
+
+            for file in files {
+                _ = w.write_fmt(format_args!("  {file}\n"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::dependency;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{github_matrix, github_output, print_services, service_entries};
+
+    fn services() -> Result<(tempfile::TempDir, Opts, Vec<Service>)> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(
+            dir.path(),
+            "services/auth/Depsfile",
+            "languages:\n  - golang\n",
+        )?;
+        create_file(dir.path(), "libs/auth/Depsfile", "")?;
+
+        let mut opts = Opts::new(dir.path(), Config::default())?;
+        opts.relative = true;
+        let services = Service::discover(&opts)?;
+
+        Ok((dir, opts, services))
+    }
+
+    #[test]
+    fn matrix() -> Result<()> {
+        let (_dir, opts, services) = services()?;
+        let json = serde_json::to_value(github_matrix(&services, &opts))?;
+
+        assert_eq!(
+            serde_json::json!({"include": [
+                {"name": "libs.auth", "path": "./libs/auth", "languages": []},
+                {"name": "services.auth", "path": "./services/auth", "languages": ["go"]},
+            ]}),
+            json
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_entries() -> Result<()> {
+        let (_dir, opts, services) = services()?;
+
+        let mut output = Vec::new();
+        github_output(&mut output, &services, &opts)?;
+        let output = String::from_utf8(output)?;
+
+        assert!(output.starts_with("matrix={\"include\":[{\"name\":\"libs.auth\""));
+        assert!(output.contains("\nany_affected=true\n"));
+        assert!(
+            output
+                .ends_with("services<<MONODEPS_EOF\n./libs/auth\n./services/auth\nMONODEPS_EOF\n")
+        );
+
+        let mut output = Vec::new();
+        github_output(&mut output, &[], &opts)?;
+        let output = String::from_utf8(output)?;
+
+        assert_eq!(
+            "matrix={\"include\":[]}\nany_affected=false\nservices<<MONODEPS_EOF\nMONODEPS_EOF\n",
+            output
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn changed_files() -> Result<()> {
+        let (dir, mut opts, _services) = services()?;
+        create_file(
+            dir.path(),
+            "services/auth/Depsfile",
+            "dependencies:\n  - ../../libs/auth\n",
+        )?;
+
+        let changed = vec![
+            "libs/auth/b.go".to_string(),
+            "services/auth/main.go".to_string(),
+            "libs/auth/a.go".to_string(),
+        ];
+        let mut services = dependency::resolve(Service::discover(&opts)?, changed, &opts)?;
+        services.sort_by(|a, b| a.path.canonicalized.cmp(&b.path.canonicalized));
+
+        opts.files = true;
+        let json = serde_json::to_value(service_entries(&services, &services, &opts)?)?;
+        assert_eq!(
+            serde_json::json!([
+                {
+                    "path": "./libs/auth",
+                    "changed_files": ["libs/auth/a.go", "libs/auth/b.go"],
+                    "dependency_files": [],
+                },
+                {
+                    "path": "./services/auth",
+                    "changed_files": ["services/auth/main.go"],
+                    "dependency_files": ["libs/auth/a.go", "libs/auth/b.go"],
+                },
+            ]),
+            json
+        );
+
+        let mut output = Vec::new();
+        print_services(&mut output, services, &opts);
+
+        assert_eq!(
+            "./libs/auth\n  libs/auth/a.go\n  libs/auth/b.go\n./services/auth\n  libs/auth/a.go\n  libs/auth/b.go\n  services/auth/main.go\n",
+            String::from_utf8(output)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_entries() -> Result<()> {
+        let (_dir, mut opts, services) = services()?;
+        opts.fingerprint = true;
+
+        let json = serde_json::to_value(service_entries(&services, &services, &opts)?)?;
+        let entries = json.as_array().cloned().unwrap_or_default();
+
+        assert_eq!(2, entries.len());
+        for entry in &entries {
+            assert_eq!(Some(64), entry["fingerprint"].as_str().map(|f| f.len()));
+            assert!(entry.get("changed_files").is_none());
+        }
+
+        // the fingerprints of affected services are based on all discovered services
+        let affected = serde_json::to_value(service_entries(&services[..1], &services, &opts)?)?;
+        assert_eq!(entries[0]["fingerprint"], affected[0]["fingerprint"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::cli::Opts;
+use crate::dependency;
+use crate::graph;
+use crate::service::{BuildTrigger, Service};
+use crate::utils::escape_html;
+
+use super::service_loc;
+
+/// Render a markdown summary of the affected `services` (e.g. for pull request comments): a table
+/// of all services with their build trigger, the `changed_files` of every service in collapsible
+/// sections and all changed files that do not belong to any service.
+///
+/// Services and files are sorted so that the output is stable across runs.
+pub(super) fn render<W>(
+    services: &[Service],
+    changed_files: &[String],
+    opts: &Opts,
+    w: &mut W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let ownership = dependency::ownership(services, changed_files, opts)?;
+
+    let mut services: Vec<_> = graph::unique_names(services)
+        .into_iter()
+        .zip(services)
+        .collect();
+    services.sort_by(|(_, a), (_, b)| a.path.canonicalized.cmp(&b.path.canonicalized));
+
+    match services.len() {
+        0 => writeln!(w, "### monodeps: no affected services")?,
+        1 => writeln!(w, "### monodeps: 1 affected service")?,
+        count => writeln!(w, "### monodeps: {count} affected services")?,
+    }
+
+    if !services.is_empty() {
+        writeln!(w)?;
+        writeln!(w, "| Service | Path | Languages | Trigger |")?;
+        writeln!(w, "| --- | --- | --- | --- |")?;
+
+        for (name, service) in &services {
+            let languages: Vec<_> = service.languages().iter().map(|l| l.to_string()).collect();
+
+            writeln!(
+                w,
+                "| {} | {} | {} | {} |",
+                escape(name),
+                code(&service_loc(service, opts)),
+                escape(&languages.join(", ")),
+                service.trigger.as_ref().map(trigger).unwrap_or_default()
+            )?;
+        }
+    }
+
+    for (name, service) in &services {
+        if let Some(files) = ownership.owned.get(&service.path.canonicalized) {
+            let title = format!("{}: {}", code(name), file_count(files.len()));
+            details(&title, files, w)?;
+        }
+    }
+
+    if !ownership.unowned.is_empty() {
+        let title = format!("Unowned files: {}", file_count(ownership.unowned.len()));
+        details(&title, &ownership.unowned, w)?;
+    }
+
+    Ok(())
+}
+
+/// Table cell of the given `BuildTrigger` including its source (e.g. the changed file) and the
+/// language that auto-discovered the dependency.
+fn trigger(trigger: &BuildTrigger) -> String {
+    let mut cell = trigger.kind().to_owned();
+
+    if let Some(source) = trigger.source() {
+        cell.push_str(&format!(" {}", code(source)));
+    }
+    if let Some(language) = trigger.language() {
+        cell.push_str(&format!(
+            " (auto-discovered: {})",
+            escape(&language.to_string())
+        ));
+    }
+
+    cell
+}
+
+/// Collapsible section with the given `title` listing all `files`.
+fn details<W>(title: &str, files: &[String], w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    writeln!(w)?;
+    writeln!(w, "<details>")?;
+    writeln!(w, "<summary>{title}</summary>")?;
+    writeln!(w)?;
+    for file in files {
+        writeln!(w, "- {}", code(file))?;
+    }
+    writeln!(w)?;
+    writeln!(w, "</details>")?;
+
+    Ok(())
+}
+
+fn file_count(count: usize) -> String {
+    if count == 1 {
+        "1 changed file".to_owned()
+    } else {
+        format!("{count} changed files")
+    }
+}
+
+/// Escape the given value to be used as text in a markdown table cell.
+fn escape(value: &str) -> String {
+    escape_html(value).replace('|', "\\|")
+}
+
+/// Render the given value as HTML `<code>` element instead of a markdown code span, so that it may
+/// contain backticks, and escape it to be used in table cells and list items alike.
+fn code(value: &str) -> String {
+    let escaped = escape_html(value)
+        .replace('`', "&#96;")
+        .replace('|', "&#124;");
+
+    format!("<code>{escaped}</code>")
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::dependency;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::render;
+
+    fn rendered(changed: &[&str]) -> Result<String> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "languages:\n  - golang\n")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "")?;
+
+        let mut opts = Opts::new(dir.path(), Config::default())?;
+        opts.relative = true;
+
+        let changed: Vec<_> = changed.iter().map(|f| f.to_string()).collect();
+        let services = Service::discover(&opts)?;
+        let affected = dependency::resolve(services, changed.clone(), &opts)?;
+
+        let mut output = Vec::new();
+        render(&affected, &changed, &opts, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn render_markdown() -> Result<()> {
+        let output = rendered(&["lib/b.go", "README.md", "lib/a.go"])?;
+
+        let expected = r#"### monodeps: 2 affected services
+
+| Service | Path | Languages | Trigger |
+| --- | --- | --- | --- |
+| api | <code>./api</code> |  | Dependency <code>lib/a.go</code> |
+| lib | <code>./lib</code> | go | FileChange |
+
+<details>
+<summary><code>lib</code>: 2 changed files</summary>
+
+- <code>lib/a.go</code>
+- <code>lib/b.go</code>
+
+</details>
+
+<details>
+<summary>Unowned files: 1 changed file</summary>
+
+- <code>README.md</code>
+
+</details>
+"#;
+        assert_eq!(expected, output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_markdown_escaped_files() -> Result<()> {
+        let output = rendered(&["lib/a`b|c.go", "<script>&\"x\".md"])?;
+
+        assert!(output.contains("| Dependency <code>lib/a&#96;b&#124;c.go</code> |"));
+        assert!(output.contains("- <code>lib/a&#96;b&#124;c.go</code>\n"));
+        assert!(output.contains("- <code>&lt;script&gt;&amp;&quot;x&quot;.md</code>\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_markdown_stable() -> Result<()> {
+        assert_eq!(
+            rendered(&["lib/a.go", "lib/b.go"])?,
+            rendered(&["lib/b.go", "lib/a.go"])?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_markdown_no_changes() -> Result<()> {
+        assert_eq!("### monodeps: no affected services\n", rendered(&[])?);
+
+        Ok(())
+    }
+}
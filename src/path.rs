@@ -2,8 +2,9 @@ use std::path::Path;
 
 use anyhow::{Result, anyhow};
 use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PathInfo {
     pub display_path: String,
     pub canonicalized: String,
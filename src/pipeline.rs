@@ -0,0 +1,128 @@
+use std::io::Write;
+
+use anyhow::{Result, anyhow, bail};
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+use crate::cli::{Opts, OutputFormat};
+use crate::graph::{Graph, Node};
+use crate::service::Service;
+use crate::template;
+
+mod buildkite;
+mod gitlab;
+
+/// Render the CI pipeline of the given (affected) services in the pipeline output format of the
+/// given `Opts`, based on the job template file given via `--pipeline-template`.
+pub fn render<W>(services: Vec<Service>, opts: &Opts, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let template_path = opts
+        .pipeline_template
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing pipeline template (see '--pipeline-template')"))?;
+    let template = std::fs::read_to_string(template_path)
+        .map_err(|err| anyhow!("failed to read pipeline template {template_path}: {err}"))?;
+
+    // the graph of the affected services alone determines the build order of the jobs
+    let graph = Graph::new(services);
+
+    match opts.output {
+        OutputFormat::GitLab => gitlab::render(&graph, &template, opts, w),
+        OutputFormat::Buildkite => buildkite::render(&graph, &template, opts, w),
+        _ => bail!("output format '{}' is no pipeline format", opts.output),
+    }
+}
+
+/// Opening and closing delimiters of the markers the placeholders of a pipeline template are
+/// replaced with before parsing it, so that they are valid plain YAML scalars.
+const MARKER: (&str, &str) = ("__monodeps_", "__");
+
+/// A pipeline template, parsed as a single YAML mapping.
+///
+/// Placeholders are substituted into the parsed keys and values instead of the template text,
+/// so that the values of a service are never interpreted as YAML.
+struct Template(Hash);
+
+impl Template {
+    fn parse(template: &str) -> Result<Self> {
+        let (open, close) = MARKER;
+        let masked = template::replace_placeholders(template, "{{", "}}", |placeholder| {
+            template::PLACEHOLDERS
+                .contains(&placeholder)
+                .then(|| format!("{open}{placeholder}{close}"))
+        });
+
+        parse(&masked).map(Self)
+    }
+
+    /// The template with all placeholders kept as they are.
+    fn raw(&self) -> Hash {
+        self.replace(&|placeholder| Some(format!("{{{{{placeholder}}}}}")))
+    }
+
+    /// The template with all placeholders replaced by the values of the service of `node`, the
+    /// `index` of the node in the graph is available as `{{index}}` (see `template::substitute`).
+    fn render(&self, node: &Node, index: usize, opts: &Opts) -> Hash {
+        let values = template::values(&node.service, &node.name, index, opts);
+        self.replace(&|placeholder| values.get(placeholder).cloned())
+    }
+
+    fn replace<F>(&self, value: &F) -> Hash
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        self.0
+            .iter()
+            .map(|(key, item)| (replace_yaml(key, value), replace_yaml(item, value)))
+            .collect()
+    }
+}
+
+/// Replace the placeholder markers in all strings of the given `yaml`.
+fn replace_yaml<F>(yaml: &Yaml, value: &F) -> Yaml
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let (open, close) = MARKER;
+
+    match yaml {
+        Yaml::String(text) => {
+            Yaml::String(template::replace_placeholders(text, open, close, value))
+        }
+        Yaml::Array(items) => Yaml::Array(items.iter().map(|i| replace_yaml(i, value)).collect()),
+        Yaml::Hash(hash) => Yaml::Hash(
+            hash.iter()
+                .map(|(k, v)| (replace_yaml(k, value), replace_yaml(v, value)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Parse the given template that is expected to be a single YAML mapping.
+fn parse(template: &str) -> Result<Hash> {
+    let mut docs = YamlLoader::load_from_str(template)
+        .map_err(|err| anyhow!("invalid pipeline template: {err}"))?;
+
+    match docs.pop() {
+        Some(Yaml::Hash(hash)) if docs.is_empty() => Ok(hash),
+        _ => bail!("invalid pipeline template: expected a single mapping"),
+    }
+}
+
+fn write_yaml<W>(yaml: &Yaml, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let mut output = String::new();
+    YamlEmitter::new(&mut output).dump(yaml)?;
+
+    // we want to omit the `---` on the first line
+    for line in output.lines().skip(1) {
+        writeln!(w, "{line}")?;
+    }
+
+    Ok(())
+}
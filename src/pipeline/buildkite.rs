@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256};
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use crate::cli::Opts;
+use crate::graph::Graph;
+use crate::lock::relative_name;
+
+use super::{Template, write_yaml};
+
+/// Key of the step that is rendered if no service is affected at all.
+const NO_CHANGES_KEY: &str = "monodeps-no-changes";
+
+/// Render a Buildkite pipeline (as used by `buildkite-agent pipeline upload`) with one step per
+/// service of the `graph`, based on the step `template` with all placeholders replaced.
+///
+/// Every step gets a stable `key` derived from the service path (unless the template defines a
+/// `key` itself) and `depends_on` the steps of all services its service depends on.
+pub(super) fn render<W>(graph: &Graph, template: &str, opts: &Opts, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let key_name = Yaml::String("key".to_owned());
+    let depends_name = Yaml::String("depends_on".to_owned());
+
+    let template = Template::parse(template)?;
+    let mut rendered_steps = Vec::new();
+    let mut keys: HashMap<&str, String> = HashMap::new();
+    let mut unique_keys = HashSet::new();
+
+    for (index, node) in graph.nodes.iter().enumerate() {
+        let mut rendered = template.render(node, index, opts);
+
+        let key = match rendered.remove(&key_name) {
+            Some(Yaml::String(key)) => key,
+            Some(_) => bail!("invalid pipeline template: 'key' has to be a string"),
+            None => step_key(&relative_name(&node.service.path, opts)),
+        };
+        if !unique_keys.insert(key.clone()) {
+            bail!("invalid pipeline template: duplicate step key '{key}' (use a placeholder)");
+        }
+
+        keys.insert(&node.id, key);
+        rendered_steps.push((node, rendered));
+    }
+
+    let mut steps = Vec::new();
+
+    for (node, mut rendered) in rendered_steps {
+        let mut step = Hash::new();
+        step.insert(
+            key_name.clone(),
+            Yaml::String(keys[node.id.as_str()].clone()),
+        );
+
+        let mut depends_on: Vec<_> = match rendered.remove(&depends_name) {
+            Some(Yaml::Array(existing)) => existing,
+            Some(Yaml::String(existing)) => vec![Yaml::String(existing)],
+            _ => Vec::new(),
+        };
+        depends_on.extend(
+            graph
+                .edges_of(&node.id)
+                .map(|edge| Yaml::String(keys[edge.to.as_str()].clone())),
+        );
+
+        step.extend(rendered);
+        if !depends_on.is_empty() {
+            step.insert(depends_name.clone(), Yaml::Array(depends_on));
+        }
+
+        steps.push(Yaml::Hash(step));
+    }
+
+    if steps.is_empty() {
+        steps.push(no_changes_step());
+    }
+
+    let mut pipeline = Hash::new();
+    pipeline.insert(Yaml::String("steps".to_owned()), Yaml::Array(steps));
+
+    write_yaml(&Yaml::Hash(pipeline), w)
+}
+
+/// Step key of the service at the given path (relative to the target directory): the path with
+/// all characters except for alphanumerics, `_` and `-` replaced by `-`. If any character had to
+/// be replaced, a short hash of the path is appended, so that the key stays unique (e.g. for
+/// `libs/auth` and `libs-auth`).
+fn step_key(relative_path: &str) -> String {
+    let mut key: String = relative_path
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    if key != relative_path {
+        let hash = Sha256::digest(relative_path.as_bytes());
+        key.push('-');
+        key.extend(hash.iter().take(4).map(|byte| format!("{byte:02x}")));
+    }
+
+    key
+}
+
+fn no_changes_step() -> Yaml {
+    let mut step = Hash::new();
+    step.insert(
+        Yaml::String("key".to_owned()),
+        Yaml::String(NO_CHANGES_KEY.to_owned()),
+    );
+    step.insert(
+        Yaml::String("command".to_owned()),
+        Yaml::String("echo 'no services affected'".to_owned()),
+    );
+    Yaml::Hash(step)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::dependency;
+    use crate::graph::Graph;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{render, step_key};
+
+    const TEMPLATE: &str = r#"
+label: ":hammer: {{path}}"
+command: just --justfile {{path}}/justfile build
+depends_on: [setup]
+"#;
+
+    fn rendered(template: &str, changed: &str) -> Result<String> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "libs/auth/Depsfile", "")?;
+        create_file(
+            dir.path(),
+            "services/auth/Depsfile",
+            "dependencies:\n  - ../../libs/auth\n",
+        )?;
+        create_file(
+            dir.path(),
+            "libs-auth/Depsfile",
+            "dependencies:\n  - ../libs/auth\n",
+        )?;
+
+        let mut opts = Opts::new(dir.path(), Config::default())?;
+        opts.relative = true;
+
+        let services = Service::discover(&opts)?;
+        let affected = dependency::resolve(services, vec![changed.to_string()], &opts)?;
+        let graph = Graph::new(affected);
+
+        let mut output = Vec::new();
+        render(&graph, template, &opts, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn render_pipeline() -> Result<()> {
+        let output = rendered(TEMPLATE, "libs/auth/lib.go")?;
+
+        let expected = r#"steps:
+  - key: libs-auth
+    label: ":hammer: ./libs-auth"
+    command: just --justfile ./libs-auth/justfile build
+    depends_on:
+      - setup
+      - libs-auth-4d59641e
+  - key: libs-auth-4d59641e
+    label: ":hammer: ./libs/auth"
+    command: just --justfile ./libs/auth/justfile build
+    depends_on:
+      - setup
+  - key: services-auth-ee989ca6
+    label: ":hammer: ./services/auth"
+    command: just --justfile ./services/auth/justfile build
+    depends_on:
+      - setup
+      - libs-auth-4d59641e
+"#;
+        assert_eq!(expected, output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_template_key() -> Result<()> {
+        let template = "key: build-{{name}}\ncommand: make\n";
+        let output = rendered(template, "libs/auth/lib.go")?;
+
+        assert!(output.contains("  - key: build-libs.auth\n"));
+        assert!(output.contains("    depends_on:\n      - build-libs.auth\n"));
+
+        let result = rendered("key: build\ncommand: make\n", "libs/auth/lib.go");
+        assert!(result.is_err_and(|err| err.to_string().contains("duplicate step key 'build'")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn step_keys() {
+        assert_eq!("api", step_key("api"));
+        assert_eq!("libs-auth", step_key("libs-auth"));
+        assert_ne!("libs-auth", step_key("libs/auth"));
+        assert_ne!(step_key("libs/auth"), step_key("libs.auth"));
+        assert_eq!(step_key("libs/auth"), step_key("libs/auth"));
+    }
+
+    #[test]
+    fn render_no_changes() -> Result<()> {
+        let output = rendered(TEMPLATE, "unknown/file")?;
+
+        assert_eq!(
+            "steps:\n  - key: monodeps-no-changes\n    command: \"echo 'no services affected'\"\n",
+            output
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{Result, anyhow, bail};
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use crate::cli::Opts;
+use crate::graph::Graph;
+
+use super::{Template, write_yaml};
+
+/// Top-level keywords of a GitLab pipeline that are no jobs.
+const GLOBAL_KEYWORDS: [&str; 10] = [
+    "default",
+    "include",
+    "stages",
+    "variables",
+    "workflow",
+    "image",
+    "services",
+    "cache",
+    "before_script",
+    "after_script",
+];
+
+/// Name of the job that is rendered if no service is affected at all, as GitLab rejects child
+/// pipelines without any job.
+const NO_CHANGES_JOB: &str = "monodeps:no-changes";
+
+/// Render a GitLab child pipeline with the jobs of the `template` for every service of the
+/// `graph`.
+///
+/// Global keywords (e.g. `stages`) and hidden jobs (starting with `.`) of the template are
+/// rendered once, all other jobs once per service with all placeholders replaced. Every job
+/// `needs` the same job of all services its service depends on.
+pub(super) fn render<W>(graph: &Graph, template: &str, opts: &Opts, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let template = Template::parse(template)?;
+    let mut pipeline = Hash::new();
+
+    for (key, value) in &template.raw() {
+        if !is_job(key) {
+            pipeline.insert(key.clone(), value.clone());
+        }
+    }
+
+    // job names by service node and index of the job in the template
+    let mut job_names: HashMap<(&str, usize), String> = HashMap::new();
+    let mut jobs = Vec::new();
+
+    for (index, node) in graph.nodes.iter().enumerate() {
+        let rendered = template.render(node, index, opts);
+
+        for (idx, (key, value)) in rendered.into_iter().filter(|(k, _)| is_job(k)).enumerate() {
+            let name = key
+                .as_str()
+                .ok_or_else(|| anyhow!("invalid job name in pipeline template"))?
+                .to_owned();
+
+            if jobs.iter().any(|(_, _, existing, _)| *existing == name) {
+                bail!(
+                    "duplicate job '{name}' in pipeline: job names of the template require a placeholder (e.g. '{{{{name}}}}')"
+                );
+            }
+
+            job_names.insert((node.id.as_str(), idx), name.clone());
+            jobs.push((node.id.as_str(), idx, name, value));
+        }
+    }
+
+    for (id, idx, name, mut job) in jobs {
+        let needs: Vec<_> = graph
+            .edges_of(id)
+            .filter_map(|edge| job_names.get(&(edge.to.as_str(), idx)))
+            .map(|name| Yaml::String(name.clone()))
+            .collect();
+
+        if let (Yaml::Hash(job), false) = (&mut job, needs.is_empty()) {
+            let key = Yaml::String("needs".to_owned());
+            match job.get_mut(&key) {
+                Some(Yaml::Array(existing)) => existing.extend(needs),
+                _ => {
+                    job.insert(key, Yaml::Array(needs));
+                }
+            }
+        }
+
+        pipeline.insert(Yaml::String(name), job);
+    }
+
+    if graph.nodes.is_empty() {
+        pipeline.insert(Yaml::String(NO_CHANGES_JOB.to_owned()), no_changes_job());
+    }
+
+    write_yaml(&Yaml::Hash(pipeline), w)
+}
+
+/// Whether the given top-level key of a pipeline is a (visible) job.
+fn is_job(key: &Yaml) -> bool {
+    match key.as_str() {
+        Some(key) => !key.starts_with('.') && !GLOBAL_KEYWORDS.contains(&key),
+        None => false,
+    }
+}
+
+fn no_changes_job() -> Yaml {
+    let mut job = Hash::new();
+    job.insert(
+        Yaml::String("stage".to_owned()),
+        Yaml::String(".pre".to_owned()),
+    );
+    job.insert(
+        Yaml::String("script".to_owned()),
+        Yaml::Array(vec![Yaml::String("echo 'no services affected'".to_owned())]),
+    );
+    Yaml::Hash(job)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use yaml_rust::YamlLoader;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::dependency;
+    use crate::graph::Graph;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::render;
+
+    const TEMPLATE: &str = r#"
+stages:
+  - build
+  - test
+
+.defaults:
+  tags: [docker]
+
+"build:{{name}}":
+  stage: build
+  script:
+    - cd {{path}}
+    - echo "{{languages}} {{trigger}}"
+
+"test:{{name}}":
+  stage: test
+  needs: ["build:{{name}}"]
+  script:
+    - just test {{path}}
+"#;
+
+    fn rendered(changed: &str, template: &str) -> Result<String> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "languages:\n  - golang\n")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "dependencies:\n  - ../api\n")?;
+
+        let mut opts = Opts::new(dir.path(), Config::default())?;
+        opts.relative = true;
+
+        let services = Service::discover(&opts)?;
+        let affected = dependency::resolve(services, vec![changed.to_string()], &opts)?;
+        let graph = Graph::new(affected);
+
+        let mut output = Vec::new();
+        render(&graph, template, &opts, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn render_pipeline() -> Result<()> {
+        let output = rendered("api/main.go", TEMPLATE)?;
+
+        let expected = r#"stages:
+  - build
+  - test
+".defaults":
+  tags:
+    - docker
+"build:api":
+  stage: build
+  script:
+    - cd ./api
+    - "echo \" FileChange\""
+"test:api":
+  stage: test
+  needs:
+    - "build:api"
+  script:
+    - just test ./api
+"build:web":
+  stage: build
+  script:
+    - cd ./web
+    - "echo \" Dependency(api/main.go)\""
+  needs:
+    - "build:api"
+"test:web":
+  stage: test
+  needs:
+    - "build:web"
+    - "test:api"
+  script:
+    - just test ./web
+"#;
+        assert_eq!(expected, output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_pipeline_languages() -> Result<()> {
+        let output = rendered("lib/lib.go", TEMPLATE)?;
+
+        assert!(output.contains("    - \"echo \\\"go FileChange\\\"\"\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_no_changes() -> Result<()> {
+        let output = rendered("unknown/file", TEMPLATE)?;
+
+        assert!(output.starts_with("stages:\n"));
+        assert!(output.contains("\"monodeps:no-changes\":\n  stage: \".pre\"\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_duplicate_jobs() {
+        let template = "build:\n  script:\n    - cd {{path}}\n";
+
+        assert!(rendered("lib/lib.go", template).is_err());
+    }
+
+    #[test]
+    fn render_values_verbatim() -> Result<()> {
+        let template = "\"build:{{name}}\":\n  script:\n    - echo {{trigger_source}}\n";
+        let changed = "lib/x: {{name}} #'\"\n  injected: true.go";
+        let output = rendered(changed, template)?;
+
+        let docs = YamlLoader::load_from_str(&output)?;
+        let job = &docs[0]["build:api"];
+
+        assert_eq!(
+            3,
+            docs[0].as_hash().map(|jobs| jobs.len()).unwrap_or_default()
+        );
+        assert!(job["injected"].is_badvalue());
+        assert_eq!(
+            Some(format!("echo {changed}").as_str()),
+            job["script"][0].as_str()
+        );
+
+        Ok(())
+    }
+}
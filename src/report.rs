@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cli::Opts;
+use crate::graph::{self, Graph, Node};
+use crate::lock::EdgeKind;
+use crate::output::service_loc;
+use crate::utils::escape_html;
+
+/// HTML template of the report, the placeholders `{{TITLE}}` and `{{DATA}}` are replaced with the
+/// target directory and the JSON encoded report data respectively.
+const TEMPLATE: &str = include_str!("report/report.html");
+
+/// Dimensions (in px) of the service nodes in the embedded graph.
+const NODE_WIDTH: usize = 180;
+const NODE_HEIGHT: usize = 36;
+const H_GAP: usize = 30;
+const V_GAP: usize = 70;
+const MARGIN: usize = 20;
+
+#[derive(Serialize)]
+struct Report {
+    target: String,
+    width: usize,
+    height: usize,
+    node_width: usize,
+    node_height: usize,
+    services: Vec<ReportService>,
+    edges: Vec<ReportEdge>,
+    changed_files: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ReportService {
+    id: String,
+    name: String,
+    path: String,
+    languages: Vec<String>,
+    /// Explicit dependencies of the Depsfile.
+    dependencies: Vec<String>,
+    auto_dependencies: Vec<ReportAutoDependency>,
+    /// Ids of the services this service depends on.
+    requires: Vec<String>,
+    /// Ids of the services depending on this service.
+    dependents: Vec<String>,
+    trigger: Option<String>,
+    /// Names of the services the build trigger propagated along, starting with this service.
+    chain: Vec<String>,
+    x: usize,
+    y: usize,
+}
+
+#[derive(Serialize)]
+struct ReportAutoDependency {
+    pattern: String,
+    language: String,
+}
+
+#[derive(Serialize)]
+struct ReportEdge {
+    from: String,
+    to: String,
+    kind: EdgeKind,
+    propagated: bool,
+}
+
+/// Write a self-contained HTML report of the service graph of the target directory into the
+/// file at `path`. If any `changed_files` are given, the affected services and their trigger
+/// chains are included as well.
+pub fn write(path: &str, opts: &Opts, changed_files: Vec<String>) -> Result<()> {
+    let affected = if changed_files.is_empty() {
+        None
+    } else {
+        Some(changed_files.clone())
+    };
+    let graph = graph::build(opts, affected)?;
+
+    let mut file = std::fs::File::create(path)?;
+    render(&graph, opts, changed_files, &mut file)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Render the HTML report of the given `graph`.
+pub fn render<W>(graph: &Graph, opts: &Opts, changed_files: Vec<String>, w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let report = report(graph, opts, changed_files);
+
+    // the JSON data is embedded into a script element that must not be closed prematurely
+    let data = serde_json::to_string(&report)?.replace("</", "<\\/");
+    let html = TEMPLATE
+        .replace("{{TITLE}}", &escape_html(&report.target))
+        .replace("{{DATA}}", &data);
+
+    w.write_all(html.as_bytes())?;
+
+    Ok(())
+}
+
+fn report(graph: &Graph, opts: &Opts, changed_files: Vec<String>) -> Report {
+    let edges = graph.service_edges();
+    let positions = layout(graph);
+
+    let rows = positions
+        .values()
+        .map(|(row, _)| row + 1)
+        .max()
+        .unwrap_or(0);
+    let columns = positions
+        .values()
+        .map(|(_, col)| col + 1)
+        .max()
+        .unwrap_or(0);
+
+    let services = graph
+        .nodes
+        .iter()
+        .map(|node| {
+            let (row, col) = positions.get(node.id.as_str()).copied().unwrap_or_default();
+
+            ReportService {
+                id: node.id.clone(),
+                name: node.name.clone(),
+                path: service_loc(&node.service, opts).into_owned(),
+                languages: node.languages().iter().map(|l| l.to_string()).collect(),
+                dependencies: node
+                    .service
+                    .depsfile
+                    .dependencies
+                    .iter()
+                    .map(|pattern| graph::pattern_label(pattern, opts))
+                    .collect(),
+                auto_dependencies: node
+                    .service
+                    .auto_dependencies
+                    .iter()
+                    .map(|auto| ReportAutoDependency {
+                        pattern: graph::pattern_label(&auto.pattern, opts),
+                        language: auto.language.to_string(),
+                    })
+                    .collect(),
+                requires: edges
+                    .iter()
+                    .filter(|edge| edge.from == node.id)
+                    .map(|edge| edge.to.clone())
+                    .collect(),
+                dependents: edges
+                    .iter()
+                    .filter(|edge| edge.to == node.id)
+                    .map(|edge| edge.from.clone())
+                    .collect(),
+                trigger: node.service.trigger.as_ref().map(|t| t.to_string()),
+                chain: trigger_chain(graph, node),
+                x: MARGIN + col * (NODE_WIDTH + H_GAP),
+                y: MARGIN + row * (NODE_HEIGHT + V_GAP),
+            }
+        })
+        .collect();
+
+    Report {
+        target: opts.target.canonicalized.clone(),
+        width: 2 * MARGIN + (columns * (NODE_WIDTH + H_GAP)).saturating_sub(H_GAP),
+        height: 2 * MARGIN + (rows * (NODE_HEIGHT + V_GAP)).saturating_sub(V_GAP),
+        node_width: NODE_WIDTH,
+        node_height: NODE_HEIGHT,
+        services,
+        edges: edges
+            .iter()
+            .map(|edge| ReportEdge {
+                from: edge.from.clone(),
+                to: edge.to.clone(),
+                kind: edge.kind.clone(),
+                propagated: graph.propagated(edge),
+            })
+            .collect(),
+        changed_files,
+    }
+}
+
+/// Assign every node a row and column of the embedded graph: services without any dependency
+/// are placed in the bottom row, every other service above all of its dependencies.
+fn layout(graph: &Graph) -> HashMap<&str, (usize, usize)> {
+    let edges = graph.service_edges();
+    let mut layers = HashMap::new();
+
+    for node in &graph.nodes {
+        layer(&node.id, &edges, &mut layers, &mut HashSet::new());
+    }
+
+    let top = layers.values().copied().max().unwrap_or(0);
+    let mut columns = vec![0; top + 1];
+    let mut positions = HashMap::new();
+
+    for node in &graph.nodes {
+        let row = top - layers.get(node.id.as_str()).copied().unwrap_or(0);
+        positions.insert(node.id.as_str(), (row, columns[row]));
+        columns[row] += 1;
+    }
+
+    positions
+}
+
+/// Length of the longest dependency path starting at the node `id`. Cycles are cut at the first
+/// node that is visited again.
+fn layer<'a>(
+    id: &'a str,
+    edges: &[&'a graph::Edge],
+    layers: &mut HashMap<&'a str, usize>,
+    visiting: &mut HashSet<&'a str>,
+) -> usize {
+    if let Some(layer) = layers.get(id) {
+        return *layer;
+    }
+    if !visiting.insert(id) {
+        return 0;
+    }
+
+    let layer = edges
+        .iter()
+        .filter(|edge| edge.from == id)
+        .map(|edge| layer(&edge.to, edges, layers, visiting) + 1)
+        .max()
+        .unwrap_or(0);
+
+    visiting.remove(id);
+    layers.insert(id, layer);
+    layer
+}
+
+/// Names of the services the build trigger of the given `node` propagated along, starting with
+/// the service itself. Unaffected services have no chain at all.
+fn trigger_chain(graph: &Graph, node: &Node) -> Vec<String> {
+    if !node.is_affected() {
+        return Vec::new();
+    }
+
+    let edges = graph.service_edges();
+    let mut chain = vec![node.name.clone()];
+    let mut visited = HashSet::from([node.id.as_str()]);
+    let mut current = node.id.as_str();
+
+    while let Some(edge) = edges.iter().find(|edge| {
+        edge.from == current && graph.propagated(edge) && !visited.contains(edge.to.as_str())
+    }) {
+        current = edge.to.as_str();
+        visited.insert(current);
+
+        if let Some(next) = graph.nodes.iter().find(|node| node.id == current) {
+            chain.push(next.name.clone());
+        }
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::graph;
+    use crate::test_util::create_file;
+
+    use super::render;
+
+    #[test]
+    fn render_report() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "dependencies:\n  - ../api\n")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let changed = vec!["lib/lib.go".to_string()];
+        let graph = graph::build(&opts, Some(changed.clone()))?;
+
+        let mut output = Vec::new();
+        render(&graph, &opts, changed, &mut output)?;
+        let html = String::from_utf8(output)?;
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("{{DATA}}"));
+
+        // self-contained: no external scripts or stylesheets
+        assert!(!html.contains("<script src"));
+        assert!(!html.contains("<link"));
+
+        let start = html
+            .find("<script type=\"application/json\" id=\"data\">")
+            .unwrap_or(0);
+        let data = &html[start..];
+        let data = &data[data.find('>').unwrap_or(0) + 1..data.find("</script>").unwrap_or(0)];
+        let json: serde_json::Value = serde_json::from_str(data)?;
+
+        let services = json["services"].as_array().cloned().unwrap_or_default();
+        assert_eq!(3, services.len());
+
+        // web -> api -> lib
+        assert_eq!("web", services[2]["name"]);
+        assert_eq!(
+            serde_json::json!(["web", "api", "lib"]),
+            services[2]["chain"]
+        );
+        assert_eq!(serde_json::json!(["service_1"]), services[2]["requires"]);
+        assert_eq!(serde_json::json!(["service_3"]), services[0]["dependents"]);
+        assert_eq!("FileChange", services[1]["trigger"]);
+
+        // dependents are placed above their dependencies
+        assert!(services[2]["y"].as_u64() < services[0]["y"].as_u64());
+        assert!(services[0]["y"].as_u64() < services[1]["y"].as_u64());
+
+        assert_eq!(serde_json::json!(["lib/lib.go"]), json["changed_files"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::cli::Opts;
+use crate::graph::{Graph, Node, Schedule};
+use crate::lock::relative_name;
+use crate::service::Service;
+
+/// The command that is executed in every affected service directory.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RunCommand {
+    /// The given program and its arguments, e.g. `monodeps run -- just build`.
+    Command(Vec<String>),
+    /// The task of the given name of every service's Depsfile (see `tasks`), that is executed via
+    /// `sh -c`. Services without that task are skipped.
+    Task(String),
+}
+
+/// Outcome of the command of a single service.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RunStatus {
+    Succeeded,
+    /// The command failed with the given exit code (if any) or could not be started at all.
+    Failed(Option<i32>),
+    /// The command was not executed because the given (affected) dependency did not succeed.
+    DependencyFailed(String),
+    /// The service does not define the requested task.
+    NoTask,
+}
+
+impl RunStatus {
+    /// Whether the services depending on this one may be executed.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, RunStatus::Succeeded | RunStatus::NoTask)
+    }
+}
+
+impl Display for RunStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunStatus::Succeeded => f.write_str("ok"),
+            RunStatus::Failed(Some(code)) => f.write_fmt(format_args!("failed (exit code {code})")),
+            RunStatus::Failed(None) => f.write_str("failed"),
+            RunStatus::DependencyFailed(dep) => {
+                f.write_fmt(format_args!("skipped (dependency {dep} failed)"))
+            }
+            RunStatus::NoTask => f.write_str("skipped (no task)"),
+        }
+    }
+}
+
+/// Result of the command of a single service.
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    /// Unique name of the service (see `graph::Node`).
+    pub name: String,
+    /// Path of the service relative to the target directory.
+    pub path: String,
+    pub status: RunStatus,
+    pub duration: Option<Duration>,
+}
+
+/// A command that is ready to be executed in a service directory.
+struct Job {
+    id: String,
+    name: String,
+    dir: String,
+    program: String,
+    args: Vec<String>,
+}
+
+/// Execute the given `command` in the directories of all given (affected) `services`, running at
+/// most `jobs` commands in parallel.
+///
+/// A service is only executed after all of the given services it depends on succeeded. If any of
+/// them fails, the service (and transitively all of its dependents) is skipped. Independent
+/// services are started in the order of their ids and dependency cycles are broken at the smallest
+/// id. All output of the commands is streamed, prefixed by the service name.
+///
+/// Returns the results of all services in the order they were finished.
+pub fn run(
+    services: Vec<Service>,
+    command: &RunCommand,
+    jobs: usize,
+    opts: &Opts,
+) -> Result<Vec<RunResult>> {
+    let graph = Graph::new(services);
+    let nodes: BTreeMap<_, _> = graph
+        .nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node))
+        .collect();
+
+    let mut schedule = Schedule::new(&graph);
+
+    let mut results: Vec<RunResult> = Vec::new();
+    // services that failed or were skipped
+    let mut failed: BTreeSet<&str> = BTreeSet::new();
+    let mut running = 0;
+    let (sender, receiver) = mpsc::channel();
+
+    let result = |id: &str, status: RunStatus, duration: Option<Duration>| RunResult {
+        name: nodes[id].name.clone(),
+        path: relative_name(&nodes[id].service.path, opts),
+        status,
+        duration,
+    };
+
+    loop {
+        // skip all services whose dependencies did not succeed
+        loop {
+            let Some((id, dependency)) = schedule.pending().find_map(|(id, dependencies)| {
+                dependencies
+                    .iter()
+                    .find(|dep| failed.contains(*dep))
+                    .map(|dep| (id, *dep))
+            }) else {
+                break;
+            };
+
+            schedule.remove(id);
+            failed.insert(id);
+            results.push(result(
+                id,
+                RunStatus::DependencyFailed(nodes[dependency].name.clone()),
+                None,
+            ));
+        }
+
+        // start all services whose dependencies are finished
+        let mut ready = schedule.ready();
+
+        if ready.is_empty() && running == 0 {
+            match schedule.cycle_start() {
+                Some(id) => {
+                    log::warn!("dependency cycle detected: starting '{}'", nodes[id].name);
+                    ready.push(id);
+                }
+                None => break,
+            }
+        }
+
+        for id in ready.into_iter().take(jobs.max(1).saturating_sub(running)) {
+            schedule.remove(id);
+
+            match job(nodes[id], command) {
+                Some(job) => {
+                    let sender = sender.clone();
+                    std::thread::spawn(move || {
+                        let started = Instant::now();
+                        let status = execute(&job);
+                        _ = sender.send((job.id, status, started.elapsed()));
+                    });
+                    running += 1;
+                }
+                None => {
+                    schedule.finish(id);
+                    results.push(result(id, RunStatus::NoTask, None));
+                }
+            }
+        }
+
+        if running == 0 {
+            continue;
+        }
+
+        // wait for the next service to finish
+        let Ok((id, status, duration)) = receiver.recv() else {
+            break;
+        };
+        running -= 1;
+
+        let Some((id, _)) = nodes.get_key_value(id.as_str()) else {
+            continue;
+        };
+        if status.is_ok() {
+            schedule.finish(id);
+        } else {
+            failed.insert(id);
+        }
+        results.push(result(id, status, Some(duration)));
+    }
+
+    Ok(results)
+}
+
+/// Write the summary table of the given results.
+pub fn summary<W>(results: &[RunResult], w: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let rows: Vec<_> = results
+        .iter()
+        .map(|result| {
+            [
+                result.name.clone(),
+                result.path.clone(),
+                result.status.to_string(),
+                result
+                    .duration
+                    .map(|duration| format!("{:.1}s", duration.as_secs_f64()))
+                    .unwrap_or_else(|| "-".to_owned()),
+            ]
+        })
+        .collect();
+
+    let header = ["SERVICE", "PATH", "STATUS", "DURATION"].map(|column| column.to_owned());
+    let widths: Vec<_> = (0..header.len())
+        .map(|column| {
+            std::iter::once(&header)
+                .chain(rows.iter())
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:width$}"))
+            .collect();
+        writeln!(w, "{}", line.join("  ").trim_end())?;
+    }
+
+    Ok(())
+}
+
+/// The job of the given node, if it has anything to execute.
+fn job(node: &Node, command: &RunCommand) -> Option<Job> {
+    let (program, args) = match command {
+        RunCommand::Command(command) => {
+            let (program, args) = command.split_first()?;
+            (program.clone(), args.to_vec())
+        }
+        RunCommand::Task(task) => {
+            let script = node.service.depsfile.tasks.get(task)?;
+            ("sh".to_owned(), vec!["-c".to_owned(), script.clone()])
+        }
+    };
+
+    Some(Job {
+        id: node.id.clone(),
+        name: node.name.clone(),
+        dir: node.service.path.canonicalized.clone(),
+        program,
+        args,
+    })
+}
+
+/// Execute the given job, streaming its output prefixed by the service name.
+fn execute(job: &Job) -> RunStatus {
+    let child = Command::new(&job.program)
+        .args(&job.args)
+        .current_dir(&job.dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("[{}] failed to start '{}': {err}", job.name, job.program);
+            return RunStatus::Failed(None);
+        }
+    };
+
+    let stdout = child
+        .stdout
+        .take()
+        .map(|stdout| stream(stdout, job.name.clone(), false));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|stderr| stream(stderr, job.name.clone(), true));
+
+    let status = child.wait();
+
+    for output in [stdout, stderr].into_iter().flatten() {
+        _ = output.join();
+    }
+
+    match status {
+        Ok(status) if status.success() => RunStatus::Succeeded,
+        Ok(status) => RunStatus::Failed(status.code()),
+        Err(err) => {
+            eprintln!("[{}] failed to wait for '{}': {err}", job.name, job.program);
+            RunStatus::Failed(None)
+        }
+    }
+}
+
+/// Stream all lines of the given `reader` to STDOUT (or STDERR) prefixed by the service `name`.
+fn stream<R>(reader: R, name: String, stderr: bool) -> std::thread::JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(|line| line.ok()) {
+            if stderr {
+                eprintln!("[{name}] {line}");
+            } else {
+                println!("[{name}] {line}");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{RunCommand, RunStatus, run, summary};
+
+    /// Statuses of the services by path.
+    type Statuses = Vec<(String, RunStatus)>;
+
+    fn statuses(command: RunCommand, jobs: usize) -> Result<Statuses> {
+        Ok(executed(command, jobs)?.0)
+    }
+
+    /// The statuses of all services and the services whose `build` task was executed, in the
+    /// order they were executed.
+    fn executed(command: RunCommand, jobs: usize) -> Result<(Statuses, Vec<String>)> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(
+            dir.path(),
+            "lib/Depsfile",
+            "tasks:\n  build: echo lib >> ../order\n  test: exit 3\n",
+        )?;
+        create_file(
+            dir.path(),
+            "api/Depsfile",
+            "dependencies:\n  - ../lib\ntasks:\n  build: echo api >> ../order\n  test: 'true'\n",
+        )?;
+        create_file(
+            dir.path(),
+            "web/Depsfile",
+            "tasks:\n  build: echo web >> ../order\n  test: 'true'\n",
+        )?;
+        create_file(dir.path(), "docs/Depsfile", "")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let mut statuses: Vec<_> = run(Service::discover(&opts)?, &command, jobs, &opts)?
+            .into_iter()
+            .map(|result| (result.path, result.status))
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let order = match std::fs::read_to_string(dir.path().join("order")) {
+            Ok(order) => order.lines().map(|line| line.to_owned()).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Ok((statuses, order))
+    }
+
+    #[test]
+    fn run_task() -> Result<()> {
+        let (statuses, order) = executed(RunCommand::Task("build".to_string()), 4)?;
+
+        assert_eq!(
+            vec![
+                ("api".to_string(), RunStatus::Succeeded),
+                ("docs".to_string(), RunStatus::NoTask),
+                ("lib".to_string(), RunStatus::Succeeded),
+                ("web".to_string(), RunStatus::Succeeded),
+            ],
+            statuses
+        );
+
+        // dependencies are always finished first
+        let position = |name| order.iter().position(|line| line == name);
+        assert_eq!(3, order.len());
+        assert!(matches!(
+            (position("lib"), position("api")),
+            (Some(lib), Some(api)) if lib < api
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_failed_dependency() -> Result<()> {
+        assert_eq!(
+            vec![
+                (
+                    "api".to_string(),
+                    RunStatus::DependencyFailed("lib".to_string())
+                ),
+                ("docs".to_string(), RunStatus::NoTask),
+                ("lib".to_string(), RunStatus::Failed(Some(3))),
+                ("web".to_string(), RunStatus::Succeeded),
+            ],
+            statuses(RunCommand::Task("test".to_string()), 1)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_command() -> Result<()> {
+        let command =
+            RunCommand::Command(vec!["sh".to_string(), "-c".to_string(), "ls".to_string()]);
+        let statuses = statuses(command, 2)?;
+
+        assert!(
+            statuses
+                .iter()
+                .all(|(_, status)| *status == RunStatus::Succeeded)
+        );
+        assert_eq!(4, statuses.len());
+
+        let unknown = RunCommand::Command(vec!["monodeps-does-not-exist".to_string()]);
+        assert!(
+            self::statuses(unknown, 2)?
+                .iter()
+                .all(|(_, status)| matches!(
+                    status,
+                    RunStatus::Failed(None) | RunStatus::DependencyFailed(_)
+                ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn summary_table() -> Result<()> {
+        let results = vec![
+            super::RunResult {
+                name: "lib".to_string(),
+                path: "libs/lib".to_string(),
+                status: RunStatus::Failed(Some(1)),
+                duration: Some(std::time::Duration::from_millis(1500)),
+            },
+            super::RunResult {
+                name: "api".to_string(),
+                path: "api".to_string(),
+                status: RunStatus::DependencyFailed("lib".to_string()),
+                duration: None,
+            },
+        ];
+
+        let mut output = Vec::new();
+        summary(&results, &mut output)?;
+
+        assert_eq!(
+            "SERVICE  PATH      STATUS                           DURATION\n\
+             lib      libs/lib  failed (exit code 1)             1.5s\n\
+             api      api       skipped (dependency lib failed)  -\n",
+            String::from_utf8(output)?
+        );
+
+        Ok(())
+    }
+}
@@ -4,12 +4,15 @@ use std::fmt::Display;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Lines};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::cache::DiscoveryCache;
 use crate::cli::Opts;
 use crate::config::{DepPattern, Depsfile, DepsfileType, Language};
 use crate::path::PathInfo;
 use anyhow::{Result, anyhow};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use walkdir::{DirEntry, WalkDir};
 
 use self::dotnet::DotnetAnalyzer;
@@ -19,7 +22,9 @@ use self::javascript::JavaScriptAnalyzer;
 use self::justfile::JustfileAnalyzer;
 use self::kustomize::KustomizeAnalyzer;
 use self::makefile::MakefileAnalyzer;
+use self::plugin::PluginAnalyzer;
 use self::proto::ProtoAnalyzer;
+use self::reference::ReferenceAnalyzer;
 
 mod dotnet;
 mod flutter;
@@ -28,49 +33,108 @@ mod javascript;
 mod justfile;
 mod kustomize;
 mod makefile;
+mod plugin;
 mod proto;
+mod reference;
 
 const SCAN_MAX_LINES: usize = 300;
 
 /// The `BuildTrigger` describes the "cause" why a service was resolved as a relevant dependency.
 #[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
 pub enum BuildTrigger {
     /// Any of the service's own files was changed.
     FileChange,
-    /// A file was changed that is a direct dependency of the service.
-    Dependency(String, bool),
+    /// A file was changed that is a direct dependency of the service. The `Language` is given if
+    /// the dependency was auto-discovered by the respective language analyzer.
+    Dependency(String, Option<Language>),
     /// The current service has a dependency on a service that itself was determined to be a
-    /// dependency ("peer dependency").
-    PeerDependency(String, bool),
+    /// dependency ("peer dependency"). The `Language` is given if the dependency was
+    /// auto-discovered.
+    PeerDependency(String, Option<Language>),
     /// The service is a valid dependency because a file was changed that is in the list of
     /// configured "global" dependencies.
     GlobalDependency,
+    /// The service depended on a service (that was determined to be a dependency) in the base
+    /// dependency graph only, e.g. before a dependency was removed.
+    BaseDependency(String),
+    /// The fingerprint of the service differs from the one of the previous manifest (see
+    /// `--since-manifest`).
+    FingerprintChange,
+    /// The service has no recorded commit it was last built from (see `--since-built`).
+    NotBuilt,
+}
+
+impl BuildTrigger {
+    /// Kind of the trigger without its source, e.g. `Peer-Dependency`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BuildTrigger::FileChange => "FileChange",
+            BuildTrigger::Dependency(_, Some(_)) => "Auto-Dependency",
+            BuildTrigger::Dependency(_, None) => "Dependency",
+            BuildTrigger::PeerDependency(_, Some(_)) => "Auto-Peer-Dependency",
+            BuildTrigger::PeerDependency(_, None) => "Peer-Dependency",
+            BuildTrigger::GlobalDependency => "Global",
+            BuildTrigger::BaseDependency(_) => "Base-Dependency",
+            BuildTrigger::FingerprintChange => "FingerprintChange",
+            BuildTrigger::NotBuilt => "NotBuilt",
+        }
+    }
+
+    /// The dependency that caused the trigger, if any.
+    pub fn source(&self) -> Option<&str> {
+        match self {
+            BuildTrigger::Dependency(dep, _)
+            | BuildTrigger::PeerDependency(dep, _)
+            | BuildTrigger::BaseDependency(dep) => Some(dep),
+            BuildTrigger::FileChange
+            | BuildTrigger::GlobalDependency
+            | BuildTrigger::FingerprintChange
+            | BuildTrigger::NotBuilt => None,
+        }
+    }
+
+    /// The `Language` whose analyzer auto-discovered the dependency that caused the trigger.
+    pub fn language(&self) -> Option<&Language> {
+        match self {
+            BuildTrigger::Dependency(_, language) | BuildTrigger::PeerDependency(_, language) => {
+                language.as_ref()
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Display for BuildTrigger {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BuildTrigger::FileChange => f.write_str("FileChange"),
-            BuildTrigger::Dependency(dep, true) => {
+            BuildTrigger::Dependency(dep, Some(_)) => {
                 f.write_fmt(format_args!("Auto-Dependency({})", dep))
             }
-            BuildTrigger::Dependency(dep, false) => {
-                f.write_fmt(format_args!("Dependency({})", dep))
-            }
-            BuildTrigger::PeerDependency(dep, true) => {
+            BuildTrigger::Dependency(dep, None) => f.write_fmt(format_args!("Dependency({})", dep)),
+            BuildTrigger::PeerDependency(dep, Some(_)) => {
                 f.write_fmt(format_args!("Auto-Peer-Dependency({})", dep))
             }
-            BuildTrigger::PeerDependency(dep, false) => {
+            BuildTrigger::PeerDependency(dep, None) => {
                 f.write_fmt(format_args!("Peer-Dependency({})", dep))
             }
             BuildTrigger::GlobalDependency => f.write_str("Global"),
+            BuildTrigger::BaseDependency(dep) => {
+                f.write_fmt(format_args!("Base-Dependency({})", dep))
+            }
+            BuildTrigger::FingerprintChange => f.write_str("FingerprintChange"),
+            BuildTrigger::NotBuilt => f.write_str("NotBuilt"),
         }
     }
 }
 
 /// Every language analyzer has to implement this trait, in order to auto-discover dependencies for
 /// a given service directory.
-trait LanguageAnalyzer {
+///
+/// Besides the built-in analyzers, custom implementations can be registered via
+/// `Opts::register_analyzer`.
+pub trait LanguageAnalyzer {
     /// Auto-discover all dependencies for the given service directory (`dir`) and using the
     /// pre-discovered, relevant file entries (`entries`).
     fn dependencies(
@@ -86,8 +150,31 @@ trait LanguageAnalyzer {
     fn file_relevant(&self, file_name: &str) -> bool;
 }
 
+/// A custom `LanguageAnalyzer` that is registered under the given `name`. All auto-discovered
+/// dependencies of it are associated with `Language::Custom(name)`.
+#[derive(Clone)]
+pub struct CustomAnalyzer {
+    pub name: String,
+    pub analyzer: Arc<dyn LanguageAnalyzer + Send + Sync>,
+}
+
+impl LanguageAnalyzer for CustomAnalyzer {
+    fn dependencies(
+        &self,
+        entries: Vec<DirEntry>,
+        dir: &str,
+        opts: &Opts,
+    ) -> Result<Vec<DepPattern>> {
+        self.analyzer.dependencies(entries, dir, opts)
+    }
+
+    fn file_relevant(&self, file_name: &str) -> bool {
+        self.analyzer.file_relevant(file_name)
+    }
+}
+
 struct Analyzer {
-    analyzers: HashMap<Language, Box<dyn LanguageAnalyzer>>,
+    analyzers: HashMap<Language, Rc<dyn LanguageAnalyzer>>,
 }
 
 impl Analyzer {
@@ -95,29 +182,93 @@ impl Analyzer {
     /// meaning you usually have to instantiate only one instance for process.
     fn new(opts: &Opts) -> Analyzer {
         // collect all language analyzers that are properly configured and enabled
-        let analyzers = Language::VALUES
+        let mut analyzers: HashMap<_, _> = Language::VALUES
             .into_iter()
             .filter(|language| opts.config.auto_discovery_enabled(language))
             .flat_map(|language| {
-                language_analyzer(language, opts).map(|analyzer| (language, analyzer))
+                language_analyzer(&language, opts).map(|analyzer| (language, analyzer))
             })
             .collect();
 
+        // external analyzer plugins as configured
+        for plugin in &opts.config.auto_discovery.plugins {
+            match PluginAnalyzer::new(plugin) {
+                Ok(analyzer) => {
+                    analyzers.insert(Language::Custom(plugin.name.clone()), Rc::new(analyzer));
+                }
+                Err(err) => {
+                    log::warn!(
+                        "failed to initialize analyzer plugin '{}': {err}",
+                        plugin.name
+                    );
+                }
+            }
+        }
+
+        // declarative, regex based analyzers as configured
+        for reference in &opts.config.auto_discovery.references {
+            match ReferenceAnalyzer::new(reference) {
+                Ok(analyzer) => {
+                    analyzers.insert(Language::Custom(reference.name.clone()), Rc::new(analyzer));
+                }
+                Err(err) => {
+                    log::warn!(
+                        "failed to initialize reference analyzer '{}': {err}",
+                        reference.name
+                    );
+                }
+            }
+        }
+
+        // custom analyzers are always enabled
+        for custom in &opts.custom_analyzers {
+            analyzers.insert(
+                Language::Custom(custom.name.clone()),
+                Rc::new(custom.clone()),
+            );
+        }
+
         Self { analyzers }
     }
 
+    /// Return all languages of custom analyzers. These are not part of any `Depsfile` but are
+    /// considered for every service that contains relevant files.
+    fn custom_languages(&self) -> Vec<Language> {
+        self.analyzers
+            .keys()
+            .filter(|language| matches!(language, Language::Custom(_)))
+            .cloned()
+            .collect()
+    }
+
     /// Gather all files that are potentially of interest of any of the enabled language analyzers.
     /// Like this we are traversing the service's directory structure only once, and can afterwards
     /// pass the collected file entries for each language analyzer separately.
-    #[allow(clippy::borrowed_box)]
     fn gather_file_candidates(
         &self,
-        analyzers: &Vec<(&Language, &Box<dyn LanguageAnalyzer>)>,
+        analyzers: &Vec<(&Language, &Rc<dyn LanguageAnalyzer>)>,
         dir: &str,
     ) -> HashMap<Language, Vec<DirEntry>> {
         let mut file_candidates = HashMap::new();
 
-        for entry in non_hidden_files(dir) {
+        // hidden files are only considered if relevant to any custom analyzer (e.g. `.env`)
+        let hidden_relevant = |file_name: &str| {
+            analyzers.iter().any(|(lang, analyzer)| {
+                matches!(lang, Language::Custom(_))
+                    && analyzer.file_relevant(&file_name.to_lowercase())
+            })
+        };
+        let entries = WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|e| {
+                !is_hidden(e)
+                    || (e.file_type().is_file()
+                        && e.file_name().to_str().is_some_and(hidden_relevant))
+            })
+            // skip errors (e.g. non permission directories)
+            .filter_map(|e| e.ok());
+
+        for entry in entries {
             let file_name = match entry.file_name().to_str().map(|name| name.to_lowercase()) {
                 Some(val) => val,
                 None => continue,
@@ -128,7 +279,9 @@ impl Analyzer {
                     continue;
                 }
 
-                let lang_candidates = file_candidates.entry(**lang).or_insert_with(Vec::new);
+                let lang_candidates = file_candidates
+                    .entry((*lang).clone())
+                    .or_insert_with(Vec::new);
                 lang_candidates.push(entry.clone());
             }
         }
@@ -139,8 +292,10 @@ impl Analyzer {
     /// Collect all auto-discoverable dependencies for the specified languages in the given service
     /// directory (`dir`).
     fn discover(&self, languages: &[Language], dir: &str, opts: &Opts) -> Vec<AutoDependency> {
+        let custom_languages = self.custom_languages();
         let analyzers: Vec<_> = languages
             .iter()
+            .chain(custom_languages.iter())
             .flat_map(|language| {
                 self.analyzers
                     .get(language)
@@ -154,13 +309,19 @@ impl Analyzer {
             .into_iter()
             .flat_map(|(language, analyzer)| {
                 let relevant_files = file_candidates.remove(language).unwrap_or_default();
+
+                // custom analyzers are only invoked if there is anything to analyze at all
+                if matches!(language, Language::Custom(_)) && relevant_files.is_empty() {
+                    return Vec::new();
+                }
+
                 let result = analyzer.dependencies(relevant_files, dir, opts);
 
                 match result {
                     Ok(deps) => deps
                         .into_iter()
                         .map(|pattern| AutoDependency {
-                            language: *language,
+                            language: language.clone(),
                             pattern,
                         })
                         .collect(),
@@ -177,42 +338,49 @@ impl Analyzer {
 }
 
 /// Initialize a new `LanguageAnalyzer` for the given `language`.
-fn language_analyzer(language: Language, opts: &Opts) -> Option<Box<dyn LanguageAnalyzer>> {
+fn language_analyzer(language: &Language, opts: &Opts) -> Option<Rc<dyn LanguageAnalyzer>> {
     match language {
-        Language::Golang => Some(Box::new(GoAnalyzer {})),
+        Language::Golang => Some(Rc::new(GoAnalyzer {})),
         Language::Dotnet => match DotnetAnalyzer::new() {
-            Ok(a) => Some(Box::new(a)),
+            Ok(a) => Some(Rc::new(a)),
             Err(err) => {
                 log::warn!("failed to initialize dependency analyzer for .NET: {err}");
                 None
             }
         },
-        Language::Flutter => Some(Box::new(FlutterAnalyzer::new(&opts.target))),
-        Language::Kustomize => Some(Box::new(KustomizeAnalyzer {})),
-        Language::JavaScript => Some(Box::new(JavaScriptAnalyzer::new(opts.target.clone()))),
-        Language::Protobuf => Some(Box::new(ProtoAnalyzer::new(opts.target.clone()))),
-        Language::Justfile => Some(Box::new(JustfileAnalyzer {})),
+        Language::Flutter => Some(Rc::new(FlutterAnalyzer::new(&opts.target))),
+        Language::Kustomize => Some(Rc::new(KustomizeAnalyzer {})),
+        Language::JavaScript => Some(Rc::new(JavaScriptAnalyzer::new(opts.target.clone()))),
+        Language::Protobuf => Some(Rc::new(ProtoAnalyzer::new(opts.target.clone()))),
+        Language::Justfile => Some(Rc::new(JustfileAnalyzer {})),
         Language::Makefile => match MakefileAnalyzer::new() {
-            Ok(a) => Some(Box::new(a)),
+            Ok(a) => Some(Rc::new(a)),
             Err(err) => {
                 log::warn!("failed to initialize dependency analyzer for Makefile: {err}");
                 None
             }
         },
+        // custom analyzers are registered via `Opts` directly
+        Language::Custom(_) => None,
     }
 }
 
 /// The `Service` is the main structure that holds all service relevant information during
 /// discovery and resolve phase.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Service {
     pub path: PathInfo,
     pub depsfile: Depsfile,
     pub auto_dependencies: Vec<AutoDependency>,
     pub trigger: Option<BuildTrigger>,
+    /// Changed files (as given) inside of the service, recorded during the resolve phase.
+    pub changed_files: Vec<String>,
+    /// Changed files (as given) matching any dependency of the service, recorded during the
+    /// resolve phase.
+    pub dependency_files: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AutoDependency {
     pub language: Language,
     pub pattern: DepPattern,
@@ -225,6 +393,20 @@ impl Display for AutoDependency {
 }
 
 impl Service {
+    /// All languages of the service, including the ones of custom analyzers that discovered any
+    /// dependency.
+    pub fn languages(&self) -> Vec<&Language> {
+        let mut languages: Vec<_> = self.depsfile.languages.iter().collect();
+
+        for auto in &self.auto_dependencies {
+            if !languages.contains(&&auto.language) {
+                languages.push(&auto.language);
+            }
+        }
+
+        languages
+    }
+
     pub fn has_trigger(&self) -> bool {
         self.trigger.is_some()
     }
@@ -307,14 +489,20 @@ impl Service {
             depsfile,
             auto_dependencies,
             trigger: None,
+            changed_files: Vec::new(),
+            dependency_files: Vec::new(),
         })
     }
 
     /// Run the main discovery routine according to the given `Opts`. In general, this would try to
     /// discover all available services in the target directory and run the discovery step for each
     /// of them (see `discover_service`).
+    ///
+    /// Unless disabled via `Opts`, the discovery results of unchanged services are taken from the
+    /// `DiscoveryCache`.
     pub fn discover(opts: &Opts) -> Result<Vec<Service>> {
         let analyzer = Analyzer::new(opts);
+        let mut cache = DiscoveryCache::load(opts);
         let root_dir = &opts.target.canonicalized;
         let mut contexts = HashMap::new();
 
@@ -337,10 +525,30 @@ impl Service {
         }
 
         // afterwards we are resolving all service contexts into actual services
-        contexts
+        let services = contexts
             .into_values()
-            .map(|ctx| Service::discover_service(&analyzer, ctx, opts))
-            .collect()
+            .map(|ctx| {
+                let filetype = ctx.filetype;
+                let depsfile_location = ctx.depsfile_location.clone();
+
+                if let Some(cached) =
+                    cache.lookup(&ctx.service_location, &depsfile_location, filetype, opts)
+                {
+                    return Ok(cached);
+                }
+
+                let service = Service::discover_service(&analyzer, ctx, opts)?;
+                cache.store(&service, &depsfile_location, filetype, opts);
+
+                Ok(service)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Err(err) = cache.persist() {
+            log::warn!("failed to write discovery cache: {err}");
+        }
+
+        Ok(services)
     }
 }
 
@@ -462,19 +670,36 @@ fn try_determine_language(entry: &DirEntry) -> Option<LanguageMatch> {
 
 /// Return a `DirEntry` iterator of all files and folders in the given directory (`dir`) that are
 /// not hidden or part of `node_modules`.
-fn non_hidden_files<P>(dir: P) -> impl IntoIterator<Item = DirEntry>
+pub(crate) fn non_hidden_files<P>(dir: P) -> impl IntoIterator<Item = DirEntry>
 where
     P: AsRef<Path>,
 {
     WalkDir::new(dir)
         .into_iter()
         // filter hidden files/directories
-        .filter_entry(|e| {
-            !e.file_name()
-                .to_str()
-                .map(|s| s.starts_with(".") || s == "node_modules")
-                .unwrap_or(false)
-        })
+        .filter_entry(|e| !is_hidden(e))
+        // skip errors (e.g. non permission directories)
+        .filter_map(|e| e.ok())
+}
+
+/// Whether the given entry is hidden or `node_modules`.
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| s.starts_with(".") || s == "node_modules")
+        .unwrap_or(false)
+}
+
+/// Return a `DirEntry` iterator of all files and folders in the given directory (`dir`) including
+/// hidden ones (e.g. `.env` or `.npmrc`), only skipping the `.git` directory and `node_modules`.
+pub(crate) fn input_files<P>(dir: P) -> impl IntoIterator<Item = DirEntry>
+where
+    P: AsRef<Path>,
+{
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git" && e.file_name() != "node_modules")
         // skip errors (e.g. non permission directories)
         .filter_map(|e| e.ok())
 }
@@ -583,7 +808,7 @@ fn parent_dir(filename: &Path) -> Option<PathBuf> {
     path.ancestors().nth(1).map(|x| x.to_owned())
 }
 
-fn parents_until_root<P>(dir: P, root_dir: &PathInfo) -> Vec<PathBuf>
+pub(crate) fn parents_until_root<P>(dir: P, root_dir: &PathInfo) -> Vec<PathBuf>
 where
     P: AsRef<Path>,
 {
@@ -691,13 +916,34 @@ mod tests {
 
     use crate::cli::Opts;
     use crate::config::{
-        AutoDiscoveryConfig, Config, DepPattern, DepsfileType, DotnetConfig, GoDepsConfig,
+        AutoDiscoveryConfig, Config, DepPattern, DepsfileType, DotnetConfig, GoDepsConfig, Language,
     };
+    use crate::dependency;
+    use crate::output::print_services;
     use crate::path::PathInfo;
     use crate::service::ServiceContext;
-    use crate::{dependency, print_services};
 
-    use super::Service;
+    use super::{LanguageAnalyzer, Service};
+
+    struct GoModAnalyzer {}
+
+    impl LanguageAnalyzer for GoModAnalyzer {
+        fn file_relevant(&self, file_name: &str) -> bool {
+            file_name == "go.mod"
+        }
+
+        fn dependencies(
+            &self,
+            _entries: Vec<walkdir::DirEntry>,
+            _dir: &str,
+            opts: &Opts,
+        ) -> Result<Vec<DepPattern>> {
+            Ok(vec![DepPattern::plain(
+                "libs/go",
+                &opts.target.canonicalized,
+            )?])
+        }
+    }
 
     fn expect_output(services: Vec<Service>, expected_services: Vec<&str>) -> Result<()> {
         let mut cursor = Cursor::new(Vec::new());
@@ -730,6 +976,8 @@ mod tests {
                     dotnet: DotnetConfig {
                         package_namespaces: vec![],
                     },
+                    plugins: vec![],
+                    references: vec![],
                 },
                 global_dependencies: vec![],
             },
@@ -737,7 +985,14 @@ mod tests {
             verbose: true,
             all: false,
             relative: false,
+            cache: false,
             supported_roots: vec![],
+            pipeline_template: None,
+            format_template: None,
+            files: false,
+            fingerprint: false,
+            shard: None,
+            custom_analyzers: vec![],
         };
 
         Ok(opts)
@@ -957,6 +1212,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn discover_services_custom_analyzer() -> Result<()> {
+        let mut opts = Opts {
+            supported_roots: vec![DepsfileType::Justfile],
+            ..mk_opts("./tests/examples/full")?
+        };
+        opts.register_analyzer("gomod", GoModAnalyzer {});
+
+        let services = Service::discover(&opts)?;
+        let custom_deps: Vec<_> = services
+            .iter()
+            .flat_map(|svc| svc.auto_dependencies.iter())
+            .filter(|auto_dep| auto_dep.language == Language::Custom("gomod".to_string()))
+            .collect();
+
+        // only service-a contains a `go.mod`
+        assert_eq!(1, custom_deps.len());
+
+        let deps = dependency::resolve(services, vec!["libs/go/lib.go".to_string()], &opts)?;
+
+        // - service-a
+        // - service-c (peer dependency of service-a)
+        assert_eq!(2, deps.len());
+        expect_output(deps, vec!["service-a", "service-c"])?;
+
+        Ok(())
+    }
+
     #[test]
     fn discover_services_makefile() -> Result<()> {
         let opts = mk_opts("./tests/examples/full")?;
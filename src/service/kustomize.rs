@@ -186,6 +186,8 @@ mod tests {
                     dotnet: DotnetConfig {
                         package_namespaces: vec![],
                     },
+                    plugins: vec![],
+                    references: vec![],
                 },
                 global_dependencies: vec![],
             },
@@ -193,7 +195,14 @@ mod tests {
             verbose: true,
             relative: false,
             all: false,
+            cache: false,
             supported_roots: vec![],
+            pipeline_template: None,
+            format_template: None,
+            files: false,
+            fingerprint: false,
+            shard: None,
+            custom_analyzers: vec![],
         };
 
         Ok(opts)
@@ -0,0 +1,291 @@
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow, bail};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use walkdir::DirEntry;
+
+use crate::cli::Opts;
+use crate::config::{DepPattern, PluginConfig, to_file_name_glob};
+
+use super::LanguageAnalyzer;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The `PluginAnalyzer` delegates the auto-discovery of dependencies to an external command. The
+/// command receives a JSON request on STDIN and is expected to reply with a JSON response on
+/// STDOUT.
+pub(super) struct PluginAnalyzer {
+    name: String,
+    files: Vec<Regex>,
+    command: Vec<String>,
+    timeout: Duration,
+}
+
+/// The request that is passed to the plugin command via STDIN.
+#[derive(Serialize)]
+struct PluginRequest<'a> {
+    name: &'a str,
+    service_dir: &'a str,
+    files: Vec<&'a str>,
+    root: &'a str,
+}
+
+/// The response that is expected from the plugin command on STDOUT.
+#[derive(Deserialize)]
+struct PluginResponse {
+    #[serde(default)]
+    dependencies: Vec<PluginDependency>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluginDependency {
+    Path(String),
+    Regex { regex: String },
+}
+
+impl PluginAnalyzer {
+    pub(super) fn new(config: &PluginConfig) -> Result<Self> {
+        let files = config
+            .files
+            .iter()
+            .map(|glob| to_file_name_glob(&glob.to_lowercase()))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            files,
+            command: config.command.clone(),
+            timeout: config.timeout,
+        })
+    }
+
+    fn run(&self, request: &PluginRequest, opts: &Opts) -> Result<PluginResponse> {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .current_dir(&opts.target.canonicalized)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow!("failed to start '{}': {err}", self.command[0]))?;
+
+        let input = serde_json::to_vec(request)?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("cannot write to plugin"))?;
+
+        // write and read in separate threads so we neither block on a plugin that does not
+        // consume its input nor on one that writes a lot of output
+        let writer = std::thread::spawn(move || {
+            _ = stdin.write_all(&input);
+        });
+        let stdout = read_to_end(child.stdout.take());
+        let stderr = read_to_end(child.stderr.take());
+
+        // on timeout we do not wait for the reader threads because the output pipes may still
+        // be held open by any sub-process of the plugin
+        let status = match wait_timeout(&mut child, self.timeout)? {
+            Some(status) => status,
+            None => bail!("timed out after {:?}", self.timeout),
+        };
+
+        _ = writer.join();
+        let output = stdout.join().unwrap_or_default();
+
+        if !status.success() {
+            let errors = stderr.join().unwrap_or_default();
+            bail!(
+                "failed with {status}: {}",
+                String::from_utf8_lossy(&errors).trim()
+            );
+        }
+
+        serde_json::from_slice(&output).map_err(|err| anyhow!("invalid response: {err}"))
+    }
+}
+
+impl LanguageAnalyzer for PluginAnalyzer {
+    fn file_relevant(&self, file_name: &str) -> bool {
+        self.files.iter().any(|glob| glob.is_match(file_name))
+    }
+
+    fn dependencies(
+        &self,
+        entries: Vec<DirEntry>,
+        dir: &str,
+        opts: &Opts,
+    ) -> Result<Vec<DepPattern>> {
+        let request = PluginRequest {
+            name: &self.name,
+            service_dir: dir,
+            files: entries
+                .iter()
+                .flat_map(|entry| entry.path().to_str())
+                .collect(),
+            root: &opts.target.canonicalized,
+        };
+
+        let response = self.run(&request, opts)?;
+
+        response
+            .dependencies
+            .into_iter()
+            .map(|dependency| match dependency {
+                // similar to `Depsfile` dependencies, paths starting with `/` are relative to
+                // the repository root, otherwise relative to the service directory
+                PluginDependency::Path(path) => match path.strip_prefix("/") {
+                    Some(root_path) => DepPattern::new(root_path, &opts.target.canonicalized),
+                    None => DepPattern::new(path, dir),
+                },
+                PluginDependency::Regex { regex } => DepPattern::regex(&regex, dir),
+            })
+            .collect()
+    }
+}
+
+fn read_to_end<R>(reader: Option<R>) -> JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut reader) = reader {
+            _ = reader.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Wait for the given `child` process to exit for at most `timeout`. In case the process did not
+/// finish in time, it is killed and `None` is returned.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<std::process::ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if Instant::now() >= deadline {
+            _ = child.kill();
+            _ = child.wait();
+            return Ok(None);
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::{Config, PluginConfig};
+    use crate::service::LanguageAnalyzer;
+
+    use super::PluginAnalyzer;
+
+    fn plugin(script: &str, timeout: Duration) -> Result<PluginAnalyzer> {
+        PluginAnalyzer::new(&PluginConfig {
+            name: "test".to_string(),
+            files: vec!["*.conf".to_string(), "BUILD".to_string()],
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            timeout,
+        })
+    }
+
+    #[test]
+    fn file_relevant() -> Result<()> {
+        let analyzer = plugin("true", Duration::from_secs(1))?;
+
+        assert!(analyzer.file_relevant("deploy.conf"));
+        assert!(analyzer.file_relevant("build"));
+        assert!(!analyzer.file_relevant("build.gradle"));
+        assert!(!analyzer.file_relevant("conf"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_dependencies() -> Result<()> {
+        let opts = Opts::new("/tmp", Config::default())?;
+        let analyzer = plugin(
+            r#"cat > /dev/null; echo '{"dependencies": ["../shared", "/Dockerfile", {"regex": "[.]sql$"}]}'"#,
+            Duration::from_secs(5),
+        )?;
+
+        let deps = analyzer.dependencies(vec![], "/tmp/services/foo", &opts)?;
+
+        assert_eq!(3, deps.len());
+        assert_eq!(Some("/tmp/services/shared"), deps[0].hash());
+        assert_eq!(Some("/tmp/Dockerfile"), deps[1].hash());
+        assert!(deps[2].is_match("/tmp/db/schema.sql"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_request() -> Result<()> {
+        let opts = Opts::new("/tmp", Config::default())?;
+
+        // the plugin echoes the plugin name from the request as a dependency
+        let analyzer = plugin(
+            r#"name=$(sed 's/.*"name":"\([^"]*\)".*/\1/'); echo "{\"dependencies\": [\"../$name\"]}""#,
+            Duration::from_secs(5),
+        )?;
+
+        let deps = analyzer.dependencies(vec![], "/tmp/services/foo", &opts)?;
+
+        assert_eq!(1, deps.len());
+        assert_eq!(Some("/tmp/services/test"), deps[0].hash());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_failure() -> Result<()> {
+        let opts = Opts::new("/tmp", Config::default())?;
+        let analyzer = plugin("echo 'broken' >&2; exit 3", Duration::from_secs(5))?;
+
+        let result = analyzer.dependencies(vec![], "/tmp/services/foo", &opts);
+
+        assert!(result.is_err());
+        assert!(result.err().unwrap().to_string().contains("broken"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_invalid_response() -> Result<()> {
+        let opts = Opts::new("/tmp", Config::default())?;
+        let analyzer = plugin("echo 'no json'", Duration::from_secs(5))?;
+
+        let result = analyzer.dependencies(vec![], "/tmp/services/foo", &opts);
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_timeout() -> Result<()> {
+        let opts = Opts::new("/tmp", Config::default())?;
+        let analyzer = plugin("sleep 5", Duration::from_millis(100))?;
+
+        let result = analyzer.dependencies(vec![], "/tmp/services/foo", &opts);
+
+        assert!(result.is_err());
+        assert!(result.err().unwrap().to_string().contains("timed out"));
+
+        Ok(())
+    }
+}
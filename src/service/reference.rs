@@ -0,0 +1,253 @@
+use std::path::Path;
+
+use anyhow::Result;
+use regex::Regex;
+use walkdir::DirEntry;
+
+use crate::cli::Opts;
+use crate::config::{DepPattern, ReferenceBase, ReferenceConfig, to_file_name_glob};
+
+use super::{LanguageAnalyzer, ReferenceLineFinder, SCAN_MAX_LINES, read_lines};
+
+/// The `ReferenceAnalyzer` is a declarative, regex based analyzer that is configured in the
+/// global configuration. It finds file references in every line of the relevant files and
+/// optionally follows these references recursively.
+pub(super) struct ReferenceAnalyzer {
+    files: Vec<Regex>,
+    regex: Regex,
+    relative_to: ReferenceBase,
+    recursive: bool,
+}
+
+impl ReferenceAnalyzer {
+    pub(super) fn new(config: &ReferenceConfig) -> Result<Self> {
+        let files = config
+            .files
+            .iter()
+            .map(|glob| to_file_name_glob(&glob.to_lowercase()))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            files,
+            regex: Regex::new(&config.regex)?,
+            relative_to: config.relative_to,
+            recursive: config.recursive,
+        })
+    }
+
+    fn extract_from_line(&self, line: &str, base_dir: &Path) -> Vec<DepPattern> {
+        self.regex
+            .captures_iter(line)
+            .flat_map(|captures| captures.get(1))
+            .flat_map(|reference| DepPattern::new(reference.as_str(), base_dir))
+            .collect()
+    }
+
+    fn base_dir<'a>(&self, file_dir: &'a Path, service_dir: &'a str, opts: &'a Opts) -> &'a Path {
+        match self.relative_to {
+            ReferenceBase::File => file_dir,
+            ReferenceBase::Service => Path::new(service_dir),
+            ReferenceBase::Root => Path::new(&opts.target.canonicalized),
+        }
+    }
+}
+
+impl LanguageAnalyzer for ReferenceAnalyzer {
+    fn file_relevant(&self, file_name: &str) -> bool {
+        self.files.iter().any(|glob| glob.is_match(file_name))
+    }
+
+    fn dependencies(
+        &self,
+        entries: Vec<DirEntry>,
+        dir: &str,
+        opts: &Opts,
+    ) -> Result<Vec<DepPattern>> {
+        let mut dependencies = Vec::new();
+
+        if self.recursive {
+            // one finder for all files, so every file is visited at most once
+            let mut finder = ReferenceLineFinder::new();
+
+            for entry in entries {
+                dependencies.extend(finder.extract_from(entry.path(), &|line, parent| {
+                    self.extract_from_line(&line, self.base_dir(parent, dir, opts))
+                })?);
+            }
+        } else {
+            for entry in entries {
+                let file_dir = entry.path().parent().unwrap_or(Path::new(dir));
+                let base_dir = self.base_dir(file_dir, dir, opts);
+                let lines = read_lines(entry.path())?.map_while(Result::ok);
+
+                for line in lines.take(SCAN_MAX_LINES) {
+                    dependencies.extend(self.extract_from_line(&line, base_dir));
+                }
+            }
+        }
+
+        Ok(dependencies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::TempDir;
+
+    use crate::cli::Opts;
+    use crate::config::{AutoDiscoveryConfig, Config, Language, ReferenceBase, ReferenceConfig};
+    use crate::service::{LanguageAnalyzer, Service};
+    use crate::test_util::create_file;
+
+    use super::ReferenceAnalyzer;
+
+    fn tmp() -> Result<TempDir> {
+        Ok(tempfile::Builder::default().prefix("mdtest").tempdir()?)
+    }
+
+    fn reference(files: &[&str], relative_to: ReferenceBase, recursive: bool) -> ReferenceConfig {
+        ReferenceConfig {
+            name: "conf".to_string(),
+            files: files.iter().map(|file| file.to_string()).collect(),
+            regex: "^include \"([^\"]+)\"".to_string(),
+            relative_to,
+            recursive,
+        }
+    }
+
+    /// The dependencies the given `reference` analyzer discovers for the service `service` of `dir`.
+    fn dependencies(reference: ReferenceConfig, dir: &TempDir) -> Result<Vec<String>> {
+        create_file(dir.path(), "service/Depsfile", "")?;
+
+        let config = Config {
+            auto_discovery: AutoDiscoveryConfig {
+                references: vec![reference],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let opts = Opts::new(dir.path(), config)?;
+
+        let mut deps: Vec<_> = Service::discover(&opts)?
+            .iter()
+            .flat_map(|svc| &svc.auto_dependencies)
+            .filter(|auto| auto.language == Language::Custom("conf".to_string()))
+            .flat_map(|auto| auto.pattern.hash().map(|hash| hash.to_owned()))
+            .map(|hash| hash.replace(&opts.target.canonicalized, ""))
+            .collect();
+
+        deps.sort();
+        deps.dedup();
+
+        Ok(deps)
+    }
+
+    #[test]
+    fn file_relevant() -> Result<()> {
+        let analyzer = ReferenceAnalyzer::new(&reference(&["*.conf"], ReferenceBase::File, false))?;
+
+        assert!(analyzer.file_relevant("deploy.conf"));
+        assert!(!analyzer.file_relevant("deploy.config"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn references_relative_to_file() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "service/deploy.conf",
+            "include \"../shared/base.conf\"\nsomething else\n",
+        )?;
+        create_file(dir.path(), "shared/base.conf", "include \"other.conf\"\n")?;
+
+        let deps = dependencies(reference(&["*.conf"], ReferenceBase::File, false), &dir)?;
+
+        assert_eq!(vec!["/shared/base.conf".to_string()], deps);
+
+        Ok(())
+    }
+
+    #[test]
+    fn references_relative_to_root() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "service/deploy.conf",
+            "include \"shared/base.conf\"\n",
+        )?;
+
+        let deps = dependencies(reference(&["*.conf"], ReferenceBase::Root, false), &dir)?;
+
+        assert_eq!(vec!["/shared/base.conf".to_string()], deps);
+
+        Ok(())
+    }
+
+    #[test]
+    fn references_hidden_files() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "service/.env",
+            "include \"../shared/env.conf\"\n",
+        )?;
+        create_file(
+            dir.path(),
+            "service/.other",
+            "include \"../shared/other\"\n",
+        )?;
+        create_file(
+            dir.path(),
+            "service/.git/deploy.conf",
+            "include \"../../shared/git\"\n",
+        )?;
+        create_file(
+            dir.path(),
+            "service/deploy.conf",
+            "include \"../shared/base.conf\"\n",
+        )?;
+
+        let deps = dependencies(
+            reference(&[".env", "*.conf"], ReferenceBase::File, false),
+            &dir,
+        )?;
+
+        assert_eq!(
+            vec![
+                "/shared/base.conf".to_string(),
+                "/shared/env.conf".to_string()
+            ],
+            deps
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn references_recursive() -> Result<()> {
+        let dir = tmp()?;
+        create_file(
+            dir.path(),
+            "service/deploy.conf",
+            "include \"../shared/base.conf\"\n",
+        )?;
+        create_file(dir.path(), "shared/base.conf", "include \"common.conf\"\n")?;
+        // cyclic reference back to the base file
+        create_file(dir.path(), "shared/common.conf", "include \"base.conf\"\n")?;
+
+        let deps = dependencies(reference(&["*.conf"], ReferenceBase::File, true), &dir)?;
+
+        assert_eq!(
+            vec![
+                "/shared/base.conf".to_string(),
+                "/shared/common.conf".to_string(),
+            ],
+            deps
+        );
+
+        Ok(())
+    }
+}
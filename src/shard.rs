@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::cli::Opts;
+use crate::graph::{self, Graph};
+use crate::lock::relative_name;
+use crate::service::{Service, non_hidden_files};
+
+/// A single shard `index` (starting at 1) out of `count` shards the affected services are
+/// partitioned into, e.g. to split them over parallel CI runners.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+    pub weight: ShardWeight,
+    /// Partition the services in topological order, meaning all (affected) dependencies of a
+    /// service are in the same or an earlier shard.
+    pub topological: bool,
+}
+
+/// How the build cost of a service is estimated in order to balance the shards.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ShardWeight {
+    /// The `weight` of the service's Depsfile, 1 otherwise.
+    #[default]
+    Depsfile,
+    /// The `weight` of the service's Depsfile, the number of files of the service otherwise.
+    Files,
+}
+
+impl Shard {
+    /// Parse the shard from the notation `i/N`, e.g. `2/4`.
+    pub fn parse(value: &str, weight: ShardWeight, topological: bool) -> Result<Self> {
+        let invalid = || anyhow!("invalid shard '{value}' (expecting 'i/N', e.g. '1/4')");
+
+        let (index, count) = value.split_once('/').ok_or_else(invalid)?;
+        let index: usize = index.trim().parse().map_err(|_| invalid())?;
+        let count: usize = count.trim().parse().map_err(|_| invalid())?;
+
+        if count == 0 || index == 0 || index > count {
+            bail!("invalid shard '{value}' (expecting 1 <= i <= N)");
+        }
+
+        Ok(Self {
+            index,
+            count,
+            weight,
+            topological,
+        })
+    }
+
+    /// Determine the services of this shard out of all given (affected) `services`.
+    ///
+    /// The partition is deterministic, so every shard can be computed independently given the same
+    /// services. By default the services are assigned to the lightest shard, starting with the
+    /// heaviest service. If `topological` is set, the services are split into consecutive chunks
+    /// of roughly the same weight in topological order (dependencies first) instead, and the
+    /// services of the shard are returned in that order.
+    pub fn partition(&self, services: Vec<Service>, opts: &Opts) -> Vec<Service> {
+        let graph = Graph::new(services);
+        let order: Vec<String> = if self.topological {
+            graph::topological_order(&graph)
+                .into_iter()
+                .map(|id| id.to_owned())
+                .collect()
+        } else {
+            graph.nodes.iter().map(|node| node.id.clone()).collect()
+        };
+
+        let weighted: Vec<_> = graph
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), self.weight_of(&node.service, opts)))
+            .collect();
+
+        let shards: BTreeMap<&str, usize> = if self.topological {
+            let weights: BTreeMap<_, _> = weighted.into_iter().collect();
+            let total: u64 = weights.values().sum::<u64>().max(1);
+            let count = self.count as u64;
+
+            let mut shards = BTreeMap::new();
+            let mut accumulated = 0;
+            for id in &order {
+                let weight = weights[id.as_str()];
+                // the shard of the "center" of the service in the accumulated weights
+                let shard = ((2 * accumulated + weight) * count / (2 * total)).min(count - 1);
+                shards.insert(id.as_str(), shard as usize);
+                accumulated += weight;
+            }
+            shards
+        } else {
+            let mut weighted = weighted;
+            weighted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+            let mut loads = vec![0u64; self.count];
+            let mut shards = BTreeMap::new();
+            for (id, weight) in weighted {
+                let (shard, _) = loads
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(index, load)| (**load, *index))
+                    .unwrap_or((0, &0));
+                loads[shard] += weight;
+                shards.insert(id, shard);
+            }
+            shards
+        };
+
+        let selected: BTreeSet<_> = shards
+            .into_iter()
+            .filter(|(_, shard)| *shard == self.index - 1)
+            .map(|(id, _)| id.to_owned())
+            .collect();
+
+        let mut nodes: BTreeMap<_, _> = graph
+            .nodes
+            .into_iter()
+            .map(|node| (node.id, node.service))
+            .collect();
+
+        order
+            .into_iter()
+            .filter(|id| selected.contains(id))
+            .filter_map(|id| nodes.remove(&id))
+            .collect()
+    }
+
+    fn weight_of(&self, service: &Service, opts: &Opts) -> u64 {
+        match (service.depsfile.weight, self.weight) {
+            (Some(weight), _) => weight.into(),
+            (None, ShardWeight::Depsfile) => 1,
+            (None, ShardWeight::Files) => {
+                let files = non_hidden_files(&service.path.canonicalized)
+                    .into_iter()
+                    .filter(|entry| entry.file_type().is_file())
+                    .count() as u64;
+                log::debug!("{}: {files} files", relative_name(&service.path, opts));
+                files.max(1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::lock::relative_name;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{Shard, ShardWeight};
+
+    /// Names of the services of every shard out of `count` shards.
+    fn shards(dir: &Path, count: usize, topological: bool) -> Result<Vec<Vec<String>>> {
+        let opts = Opts::new(dir, Config::default())?;
+        let services = Service::discover(&opts)?;
+
+        (1..=count)
+            .map(|index| {
+                let shard = Shard::parse(
+                    &format!("{index}/{count}"),
+                    ShardWeight::Depsfile,
+                    topological,
+                )?;
+                Ok(shard
+                    .partition(services.clone(), &opts)
+                    .iter()
+                    .map(|svc| relative_name(&svc.path, &opts))
+                    .collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_shard() {
+        let shard = Shard::parse("2/4", ShardWeight::Depsfile, false).unwrap();
+        assert_eq!((2, 4), (shard.index, shard.count));
+
+        assert!(Shard::parse("0/4", ShardWeight::Depsfile, false).is_err());
+        assert!(Shard::parse("5/4", ShardWeight::Depsfile, false).is_err());
+        assert!(Shard::parse("1/0", ShardWeight::Depsfile, false).is_err());
+        assert!(Shard::parse("1", ShardWeight::Depsfile, false).is_err());
+        assert!(Shard::parse("a/b", ShardWeight::Depsfile, false).is_err());
+    }
+
+    #[test]
+    fn balanced_shards() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "a/Depsfile", "weight: 4\n")?;
+        create_file(dir.path(), "b/Depsfile", "weight: 3\n")?;
+        create_file(dir.path(), "c/Depsfile", "weight: 2\n")?;
+        create_file(dir.path(), "d/Depsfile", "weight: 1\n")?;
+        create_file(dir.path(), "e/Depsfile", "")?;
+
+        // a=4 | b=3 + e=1 | c=2 + d=1
+        assert_eq!(
+            vec![vec!["a"], vec!["b", "e"], vec!["c", "d"]],
+            shards(dir.path(), 3, false)?
+        );
+
+        // more shards than services
+        let all = shards(dir.path(), 7, false)?;
+        assert_eq!(5, all.iter().filter(|shard| !shard.is_empty()).count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn topological_shards() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "auth/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../auth\n")?;
+        create_file(dir.path(), "web/Depsfile", "dependencies:\n  - ../api\n")?;
+
+        assert_eq!(
+            vec![vec!["lib", "auth"], vec!["api", "web"]],
+            shards(dir.path(), 2, true)?
+        );
+        assert_eq!(
+            vec![vec!["lib", "auth", "api", "web"]],
+            shards(dir.path(), 1, true)?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Opts;
+use crate::dependency;
+use crate::git;
+use crate::graph::Graph;
+use crate::lock::relative_name;
+use crate::service::{BuildTrigger, Service};
+
+/// File name of the build state that is used if no other file is given via `--state`.
+pub const STATE_FILE: &str = "monodeps-state.json";
+
+/// The version of the build state format.
+const STATE_VERSION: u32 = 1;
+
+/// The `BuildState` records the git commit every service was last successfully built from, by
+/// the service path relative to the target directory.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BuildState {
+    pub version: u32,
+    pub services: BTreeMap<String, String>,
+}
+
+impl BuildState {
+    /// Load the build state from the given file. A missing file is an empty build state, meaning
+    /// no service was built yet.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self {
+                version: STATE_VERSION,
+                services: BTreeMap::new(),
+            });
+        }
+
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read build state {}: {err}", path.display()))?;
+        let state: BuildState = serde_json::from_str(&content)
+            .map_err(|err| anyhow!("invalid build state {}: {err}", path.display()))?;
+
+        if state.version != STATE_VERSION {
+            bail!(
+                "unsupported build state version {} (expected {STATE_VERSION})",
+                state.version
+            );
+        }
+
+        Ok(state)
+    }
+
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+
+        std::fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// Record that the given `service` (name or path relative to the target directory) was built
+    /// from the git `revision`, which is resolved to its commit hash.
+    pub fn mark_built(&mut self, service: &str, revision: &str, opts: &Opts) -> Result<()> {
+        let graph = Graph::new(Service::discover(opts)?);
+        let node = graph
+            .find(service, opts)
+            .ok_or_else(|| anyhow!("unknown service '{service}'"))?;
+        let commit = git::commit(&opts.target.canonicalized, revision)?;
+
+        self.services
+            .insert(relative_name(&node.service.path, opts), commit);
+
+        Ok(())
+    }
+
+    /// Determine all of the given (discovered) `services` that are affected by the changes since
+    /// the commit they were last built from. The changed files since every recorded commit are
+    /// determined via git and resolved against all services, as if they were given via STDIN.
+    ///
+    /// Services without any recorded commit have never been built and are triggered by a
+    /// `BuildTrigger::NotBuilt`.
+    pub fn affected(&self, services: Vec<Service>, opts: &Opts) -> Result<Vec<Service>> {
+        // services that were built from the same commit share the same changed files
+        let mut by_commit: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let mut affected = Vec::new();
+
+        for svc in &services {
+            let name = relative_name(&svc.path, opts);
+            match self.services.get(&name) {
+                Some(commit) => by_commit.entry(commit).or_default().push(name),
+                None => {
+                    let mut svc = svc.clone();
+                    svc.trigger(BuildTrigger::NotBuilt);
+                    affected.push(svc);
+                }
+            }
+        }
+
+        for (commit, names) in by_commit {
+            let changed_files = git::changed_files(&opts.target.canonicalized, commit)?;
+            log::debug!(
+                "{} changed files since {commit} for {}",
+                changed_files.len(),
+                names.join(", ")
+            );
+
+            let resolved = dependency::resolve(services.clone(), changed_files, opts)?;
+            affected.extend(
+                resolved
+                    .into_iter()
+                    .filter(|svc| names.contains(&relative_name(&svc.path, opts))),
+            );
+        }
+
+        Ok(affected)
+    }
+}
+
+/// Path of the build state file: the given `path` or `STATE_FILE` in the target directory.
+pub fn path<P>(target: P, path: Option<String>) -> String
+where
+    P: AsRef<Path>,
+{
+    path.unwrap_or_else(|| target.as_ref().join(STATE_FILE).display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::lock::relative_name;
+    use crate::service::{BuildTrigger, Service};
+    use crate::test_util::{create_file, git};
+
+    use super::BuildState;
+
+    fn affected(state: &BuildState, opts: &Opts) -> Result<Vec<(String, BuildTrigger)>> {
+        let mut affected: Vec<_> = state
+            .affected(Service::discover(opts)?, opts)?
+            .into_iter()
+            .filter_map(|svc| {
+                svc.trigger
+                    .clone()
+                    .map(|trigger| (relative_name(&svc.path, opts), trigger))
+            })
+            .collect();
+        affected.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(affected)
+    }
+
+    #[test]
+    fn affected_since_built() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "")?;
+        create_file(dir.path(), "lib/lib.go", "package lib")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+        create_file(dir.path(), "web/Depsfile", "")?;
+        create_file(dir.path(), "web/index.ts", "export {}")?;
+        git(dir.path(), &["init", "-q"])?;
+        git(dir.path(), &["add", "-A"])?;
+        git(dir.path(), &["commit", "-q", "-m", "first"])?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let mut state = BuildState::load(dir.path().join("state.json"))?;
+
+        // nothing was built yet
+        assert_eq!(3, affected(&state, &opts)?.len());
+
+        state.mark_built("lib", "HEAD", &opts)?;
+        state.mark_built("./api/", "HEAD", &opts)?;
+        assert!(state.mark_built("unknown", "HEAD", &opts).is_err());
+        assert!(state.mark_built("lib", "does-not-exist", &opts).is_err());
+
+        create_file(dir.path(), "web/index.ts", "export const changed = 1")?;
+        git(dir.path(), &["commit", "-q", "-a", "-m", "second"])?;
+
+        // 'web' lags behind, 'lib' and 'api' are up to date
+        state.mark_built("web", "HEAD~1", &opts)?;
+        assert_eq!(
+            vec![("web".to_string(), BuildTrigger::FileChange)],
+            affected(&state, &opts)?
+        );
+
+        create_file(dir.path(), "lib/lib.go", "package lib // changed")?;
+        git(dir.path(), &["commit", "-q", "-a", "-m", "third"])?;
+        state.mark_built("web", "HEAD", &opts)?;
+
+        let affected = affected(&state, &opts)?;
+        assert_eq!(
+            vec!["api", "lib"],
+            affected.iter().map(|a| &a.0).collect::<Vec<_>>()
+        );
+
+        // state round trip
+        let path = dir.path().join("state.json");
+        state.write(&path)?;
+        assert_eq!(state, BuildState::load(&path)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_version() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        let path = dir.path().join("state.json");
+        std::fs::write(&path, "{\"version\": 99, \"services\": {}}")?;
+
+        assert!(BuildState::load(&path).is_err());
+
+        Ok(())
+    }
+}
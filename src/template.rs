@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::cli::Opts;
+use crate::graph;
+use crate::output::service_loc;
+use crate::service::Service;
+
+/// Sections of a template besides the item template itself.
+const SECTIONS: [&str; 3] = ["header", "separator", "footer"];
+
+/// User-defined output template (see `--format-template` and `--template-file`).
+///
+/// The `item` is rendered once per service, joined by the `separator` and enclosed by the
+/// `header` and `footer`. In the template text the sections are given as
+/// `{{#header}}...{{/header}}`, `{{#separator}}...{{/separator}}` and `{{#footer}}...{{/footer}}`,
+/// everything else is the item template.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Template {
+    pub header: String,
+    pub item: String,
+    pub separator: String,
+    pub footer: String,
+}
+
+impl Template {
+    /// Parse the template text, extracting all sections.
+    pub fn parse(text: &str) -> Self {
+        let mut item = text.to_owned();
+        let mut sections = SECTIONS.map(|name| {
+            let open = format!("{{{{#{name}}}}}");
+            let close = format!("{{{{/{name}}}}}");
+
+            match (item.find(&open), item.find(&close)) {
+                (Some(start), Some(end)) if start < end => {
+                    let content = item[start + open.len()..end].to_owned();
+                    item.replace_range(start..end + close.len(), "");
+                    content
+                }
+                _ => String::new(),
+            }
+        });
+
+        Self {
+            item,
+            footer: std::mem::take(&mut sections[2]),
+            separator: std::mem::take(&mut sections[1]),
+            header: std::mem::take(&mut sections[0]),
+        }
+    }
+
+    /// Parse a template given on the command line, interpreting the escape sequences `\n`, `\t`
+    /// and `\\`.
+    pub fn parse_inline(text: &str) -> Self {
+        let mut unescaped = String::with_capacity(text.len());
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some('n')) => unescaped.push('\n'),
+                ('\\', Some('t')) => unescaped.push('\t'),
+                ('\\', Some('\\')) => unescaped.push('\\'),
+                _ => {
+                    unescaped.push(c);
+                    continue;
+                }
+            }
+            chars.next();
+        }
+
+        Self::parse(&unescaped)
+    }
+
+    /// Render the given services, sorted by their path.
+    pub fn render<W>(&self, services: &[Service], opts: &Opts, w: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        let mut services: Vec<_> = graph::unique_names(services)
+            .into_iter()
+            .zip(services)
+            .collect();
+        services.sort_by(|(_, a), (_, b)| a.path.canonicalized.cmp(&b.path.canonicalized));
+
+        write!(w, "{}", self.header)?;
+
+        for (idx, (name, service)) in services.iter().enumerate() {
+            if idx > 0 {
+                write!(w, "{}", self.separator)?;
+            }
+            write!(w, "{}", substitute(&self.item, service, name, idx, opts))?;
+        }
+
+        write!(w, "{}", self.footer)?;
+
+        Ok(())
+    }
+}
+
+/// Names of all placeholders (see `substitute`).
+pub(crate) const PLACEHOLDERS: [&str; 8] = [
+    "path",
+    "relative_path",
+    "name",
+    "languages",
+    "trigger",
+    "trigger_kind",
+    "trigger_source",
+    "index",
+];
+
+/// Replace all placeholders of the given `template` with the values of the `service`:
+///
+/// - `{{path}}`: path of the service (relative with `--relative`)
+/// - `{{relative_path}}`: path of the service relative to the target directory
+/// - `{{name}}`: short but unique name of the service
+/// - `{{languages}}`: comma separated list of the service's languages
+/// - `{{trigger}}`: the `BuildTrigger` of the service
+/// - `{{trigger_kind}}`: kind of the `BuildTrigger` (e.g. `Peer-Dependency`)
+/// - `{{trigger_source}}`: the dependency that caused the `BuildTrigger`
+/// - `{{index}}`: zero-based index of the service in the output
+pub(crate) fn substitute(
+    template: &str,
+    service: &Service,
+    name: &str,
+    index: usize,
+    opts: &Opts,
+) -> String {
+    let values = values(service, name, index, opts);
+
+    replace_placeholders(template, "{{", "}}", |placeholder| {
+        values.get(placeholder).cloned()
+    })
+}
+
+/// The values of all placeholders (see `substitute`) for the given `service`.
+pub(crate) fn values(
+    service: &Service,
+    name: &str,
+    index: usize,
+    opts: &Opts,
+) -> HashMap<&'static str, String> {
+    let languages: Vec<_> = service.languages().iter().map(|l| l.to_string()).collect();
+    let trigger = service.trigger.as_ref();
+
+    HashMap::from([
+        ("path", service_loc(service, opts).into_owned()),
+        ("relative_path", service.path.relative_to(&opts.target)),
+        ("name", name.to_owned()),
+        ("languages", languages.join(",")),
+        (
+            "trigger",
+            trigger.map(|t| t.to_string()).unwrap_or_default(),
+        ),
+        (
+            "trigger_kind",
+            trigger.map(|t| t.kind()).unwrap_or_default().to_owned(),
+        ),
+        (
+            "trigger_source",
+            trigger
+                .and_then(|t| t.source())
+                .unwrap_or_default()
+                .to_owned(),
+        ),
+        ("index", index.to_string()),
+    ])
+}
+
+/// Replace every placeholder enclosed by `open` and `close` in the given `text` with its value
+/// as returned by `value`, in a single pass: inserted values are never scanned for placeholders
+/// again. Unknown placeholders (`value` returns `None`) are kept as they are.
+pub(crate) fn replace_placeholders<F>(text: &str, open: &str, close: &str, value: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(open) {
+        let (before, candidate) = rest.split_at(start);
+        result.push_str(before);
+
+        let replaced = candidate[open.len()..].find(close).and_then(|end| {
+            let placeholder = &candidate[open.len()..open.len() + end];
+            value(placeholder).map(|value| (value, open.len() + end + close.len()))
+        });
+
+        match replaced {
+            Some((value, consumed)) => {
+                result.push_str(&value);
+                rest = &candidate[consumed..];
+            }
+            None => {
+                result.push_str(open);
+                rest = &candidate[open.len()..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::dependency;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::Template;
+
+    fn rendered(template: Template, changed: Option<&str>) -> Result<String> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "lib/Depsfile", "languages:\n  - golang\n")?;
+        create_file(dir.path(), "api/Depsfile", "dependencies:\n  - ../lib\n")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let mut services = Service::discover(&opts)?;
+        if let Some(changed) = changed {
+            services = dependency::resolve(services, vec![changed.to_string()], &opts)?;
+        }
+
+        let mut output = Vec::new();
+        template.render(&services, &opts, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn parse_sections() {
+        let template = Template::parse(
+            "{{#header}}[{{/header}}\"{{name}}\"{{#separator}}, {{/separator}}{{#footer}}]{{/footer}}",
+        );
+
+        assert_eq!("[", template.header);
+        assert_eq!("\"{{name}}\"", template.item);
+        assert_eq!(", ", template.separator);
+        assert_eq!("]", template.footer);
+    }
+
+    #[test]
+    fn parse_inline_escapes() {
+        let template = Template::parse_inline("{{path}}\\t{{name}}\\n\\\\n");
+
+        assert_eq!("{{path}}\t{{name}}\n\\n", template.item);
+        assert_eq!("", template.header);
+    }
+
+    #[test]
+    fn render_shell_array() -> Result<()> {
+        let template = Template::parse_inline(
+            "{{#header}}services=({{/header}}\"{{relative_path}}\"{{#separator}} {{/separator}}{{#footer}})\\n{{/footer}}",
+        );
+
+        assert_eq!(
+            "services=(\"./api\" \"./lib\")\n",
+            rendered(template, None)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_csv() -> Result<()> {
+        let template = Template::parse(
+            "{{#header}}index,name,languages,kind,source\n{{/header}}{{index}},{{name}},{{languages}},{{trigger_kind}},{{trigger_source}}\n",
+        );
+
+        assert_eq!(
+            "index,name,languages,kind,source\n0,api,,Dependency,lib/lib.go\n1,lib,go,FileChange,\n",
+            rendered(template, Some("lib/lib.go"))?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_values_verbatim() -> Result<()> {
+        let template = Template::parse("{{name}}: {{trigger_source}} {{unknown}}\n");
+
+        assert_eq!(
+            "api: lib/{{name}}: #'\"x.go {{unknown}}\nlib:  {{unknown}}\n",
+            rendered(template, Some("lib/{{name}}: #'\"x.go"))?
+        );
+
+        Ok(())
+    }
+}
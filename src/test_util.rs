@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Result;
+
+/// Create the file `name` (including its parent directories) in `dir` with the given `content`.
+pub(crate) fn create_file(dir: &Path, name: &str, content: &str) -> Result<()> {
+    let path = dir.join(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Run git with the given `args` in `dir` and assert that it succeeded.
+pub(crate) fn git(dir: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .output()?
+        .status;
+    assert!(status.success(), "git {args:?} failed");
+    Ok(())
+}
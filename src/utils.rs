@@ -36,3 +36,13 @@ pub fn yaml_str_list(yaml: &Yaml) -> Vec<String> {
         .filter(|value| !value.is_empty())
         .collect()
 }
+
+/// Escape the given value to be used in HTML or XML text and (quoted) attribute values.
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::cache::DiscoveryInputs;
+use crate::cli::Opts;
+use crate::dependency;
+use crate::path::PathInfo;
+use crate::service::Service;
+
+/// Duration to wait for further filesystem events before the affected services are determined.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Names of service root files that require a new service discovery whenever they change.
+const SERVICE_ROOTS: [&str; 4] = ["Depsfile", "Buildfile.yaml", "justfile", "Makefile"];
+
+/// Names of language manifest files that require a new service discovery whenever they change.
+const MANIFESTS: [&str; 6] = [
+    "go.mod",
+    "package.json",
+    "pubspec.yaml",
+    "kustomization.yaml",
+    "kustomization.yml",
+    "analysis_options.yaml",
+];
+
+/// Watch the target directory for file changes and invoke `on_update` with the list of affected
+/// services and all discovered services whenever the set of changed files was updated. The
+/// changed files are accumulated from the start of the watch.
+///
+/// Services are discovered once at the beginning and re-discovered whenever any input of the
+/// discovery (see `DiscoveryInputs`) changed or a new service root file or language manifest
+/// appeared. This function only returns on error.
+pub fn run<F>(opts: &Opts, mut on_update: F) -> Result<()>
+where
+    F: FnMut(Vec<Service>, &[Service]),
+{
+    let mut services = Service::discover(opts)?;
+    let mut inputs = DiscoveryInputs::new(&services, opts);
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(
+        Path::new(&opts.target.canonicalized),
+        RecursiveMode::Recursive,
+    )?;
+
+    let mut changed_files = BTreeSet::new();
+
+    loop {
+        // block until anything happens and collect all events that follow shortly after
+        let mut events = vec![rx.recv().map_err(|_| anyhow!("file watcher stopped"))?];
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+            events.push(event);
+        }
+
+        let mut updated = false;
+        let mut rediscover = false;
+
+        for event in events {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    log::warn!("failed to watch files: {err}");
+                    continue;
+                }
+            };
+
+            // reading files (e.g. during discovery) is no change at all
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+                if let Some(relative) = relevant_change(&path, &opts.target) {
+                    rediscover |= requires_rediscovery(&path, &inputs);
+                    updated |= changed_files.insert(relative);
+                }
+            }
+        }
+
+        if rediscover {
+            log::debug!("watch: re-discovering services");
+
+            match Service::discover(opts) {
+                Ok(rediscovered) => {
+                    inputs = DiscoveryInputs::new(&rediscovered, opts);
+                    services = rediscovered;
+                }
+                Err(err) => log::warn!("failed to re-discover services: {err}"),
+            }
+        }
+
+        if updated || rediscover {
+            match dependency::resolve(
+                services.clone(),
+                changed_files.iter().cloned().collect(),
+                opts,
+            ) {
+                Ok(affected) => on_update(affected, &services),
+                Err(err) => log::warn!("failed to determine affected services: {err}"),
+            }
+        }
+    }
+}
+
+/// Determine whether the changed `path` is relevant for change detection, meaning it is part of
+/// the target directory and neither hidden nor part of `node_modules`. Relevant paths are
+/// returned relative to the target directory.
+fn relevant_change(path: &Path, target: &PathInfo) -> Option<String> {
+    let relative = path.strip_prefix(&target.canonicalized).ok()?;
+
+    let ignored = relative.components().any(|component| match component {
+        Component::Normal(name) => name
+            .to_str()
+            .map(|name| name.starts_with(".") || name == "node_modules")
+            .unwrap_or(true),
+        _ => false,
+    });
+
+    if ignored || relative.as_os_str().is_empty() {
+        return None;
+    }
+
+    relative.to_str().map(|path| path.to_owned())
+}
+
+/// Determine whether a change to the given `path` may change the discovered services or their
+/// dependencies, meaning it is an input of the current discovery or may be the root of a new
+/// service.
+fn requires_rediscovery(path: &Path, inputs: &DiscoveryInputs) -> bool {
+    if inputs.contains(path) {
+        return true;
+    }
+
+    let file_name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    SERVICE_ROOTS.contains(&file_name)
+        || MANIFESTS.contains(&file_name)
+        || file_name.ends_with(".csproj")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    use crate::cache::DiscoveryInputs;
+    use crate::cli::Opts;
+    use crate::config::Config;
+    use crate::path::PathInfo;
+    use crate::service::Service;
+    use crate::test_util::create_file;
+
+    use super::{relevant_change, requires_rediscovery};
+
+    #[test]
+    fn relevant_change_relative() -> Result<()> {
+        let target = PathInfo::new("/tmp/repo", "")?;
+
+        assert_eq!(
+            Some("services/auth/main.go".to_string()),
+            relevant_change(Path::new("/tmp/repo/services/auth/main.go"), &target)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn relevant_change_ignored() -> Result<()> {
+        let target = PathInfo::new("/tmp/repo", "")?;
+
+        // hidden files and directories
+        assert_eq!(
+            None,
+            relevant_change(Path::new("/tmp/repo/.git/index"), &target)
+        );
+        assert_eq!(
+            None,
+            relevant_change(
+                Path::new("/tmp/repo/.monodeps-cache/discovery.json"),
+                &target
+            )
+        );
+        assert_eq!(
+            None,
+            relevant_change(Path::new("/tmp/repo/services/.main.go.swp"), &target)
+        );
+
+        // node modules
+        assert_eq!(
+            None,
+            relevant_change(
+                Path::new("/tmp/repo/web/node_modules/react/index.js"),
+                &target
+            )
+        );
+
+        // outside of target directory
+        assert_eq!(None, relevant_change(Path::new("/tmp/other/file"), &target));
+        assert_eq!(None, relevant_change(Path::new("/tmp/repo"), &target));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rediscovery() -> Result<()> {
+        let dir = tempfile::Builder::default().prefix("mdtest").tempdir()?;
+        create_file(dir.path(), "Directory.Build.props", "")?;
+        create_file(dir.path(), "service/Depsfile", "languages: [justfile]\n")?;
+        create_file(
+            dir.path(),
+            "service/justfile",
+            "import '../shared/common.just'\n",
+        )?;
+        create_file(dir.path(), "shared/common.just", "")?;
+        create_file(dir.path(), "shared/README.md", "")?;
+
+        let opts = Opts::new(dir.path(), Config::default())?;
+        let inputs = DiscoveryInputs::new(&Service::discover(&opts)?, &opts);
+        let path = |file: &str| Path::new(&opts.target.canonicalized).join(file);
+
+        // inputs of the current discovery
+        assert!(requires_rediscovery(&path("service/Depsfile"), &inputs));
+        assert!(requires_rediscovery(&path("service/api.proto"), &inputs));
+        assert!(requires_rediscovery(
+            &path("Directory.Build.props"),
+            &inputs
+        ));
+        assert!(requires_rediscovery(&path("shared/common.just"), &inputs));
+
+        // roots of new services
+        assert!(requires_rediscovery(&path("other/go.mod"), &inputs));
+        assert!(requires_rediscovery(&path("other/Other.csproj"), &inputs));
+
+        assert!(!requires_rediscovery(&path("shared/README.md"), &inputs));
+        assert!(!requires_rediscovery(&path("other/depsfile.md"), &inputs));
+
+        Ok(())
+    }
+}